@@ -1,91 +1,403 @@
-use std::fs::{self, read_to_string};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::join_all;
 
-use crate::todo_parser::{find_todos, remove_todos};
-use crate::{config::Config, todo_parser::mark_todos};
+use crate::todo_parser::{remove_todos, TodoReader};
+use crate::{
+    api::{ApiError, IssueState},
+    todo::{IssueId, Todo},
+};
+use crate::{
+    config::{Config, Policy, Remote, Scope, Target},
+    todo_parser::mark_todos,
+};
 use crate::{
-    project::{add_to_git, find_files},
-    Result,
+    project::{blame, find_files},
+    source::{Source, SourceError},
+    stats::Stats,
+    Result, TodoError,
 };
 
-pub fn todo_files(conf: &Config) {
-    let files = find_files(&conf.root, &conf.filter_fn).unwrap();
+/// A file that can't be read, like a binary file
+#[derive(Debug)]
+pub struct Skipped {
+    /// Relative to the root
+    pub path: PathBuf,
+    pub error: SourceError,
+}
+
+/// The todos of a file
+#[derive(Debug)]
+pub struct FileTodos {
+    /// Relative to the root
+    pub path: PathBuf,
+    pub todos: Vec<Todo>,
+}
+
+/// The todos of all searched files
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Files without todos are left out
+    pub files: Vec<FileTodos>,
+    pub skipped: Vec<Skipped>,
+}
 
-    for path in files {
-        println!("{}", path.strip_prefix(&conf.root).unwrap().display())
+/// A file that `report` or `purge` changed
+#[derive(Debug)]
+pub struct Changed {
+    /// Relative to the root
+    pub path: PathBuf,
+    /// The references of the changed todos, like `#12`
+    pub issues: Vec<String>,
+}
+
+/// What `report` or `purge` did
+#[derive(Debug, Default)]
+pub struct Update {
+    pub changed: Vec<Changed>,
+    /// Todos whose issue was created, but the file was changed since it was read
+    pub unmarked: Vec<(PathBuf, Todo)>,
+    pub skipped: Vec<Skipped>,
+    /// Todos whose issue couldn't be looked up, so they were kept
+    pub unchecked: Vec<Unchecked>,
+    /// Todos whose issue couldn't be created
+    pub failed: Vec<Failed>,
+}
+
+/// A todo that couldn't be reported
+#[derive(Debug)]
+pub struct Failed {
+    /// Relative to the root
+    pub path: PathBuf,
+    pub todo: Todo,
+    pub error: ApiError,
+}
+
+/// A reported todo whose repo can't be reached
+#[derive(Debug)]
+pub struct Unchecked {
+    /// Relative to the root
+    pub path: PathBuf,
+    pub todo: Todo,
+    pub reason: String,
+}
+
+impl Update {
+    fn add(&mut self, relative: &Path, todos: &[Todo], changed: &[u32]) {
+        if changed.is_empty() {
+            return;
+        }
+
+        let issues = todos
+            .iter()
+            .filter(|t| changed.contains(&t.line))
+            .filter_map(|t| t.issue_ref())
+            .collect();
+
+        self.changed.push(Changed {
+            path: relative.to_owned(),
+            issues,
+        })
     }
 }
 
-pub fn list_todos(conf: &Config, reported: bool, unreported: bool) -> Result<()> {
-    let files = find_files(&conf.root, &conf.filter_fn).unwrap();
+/// The path relative to the root, paths outside of it are kept
+fn relative<'a>(conf: &Config, path: &'a Path) -> &'a Path {
+    path.strip_prefix(&conf.root).unwrap_or(path)
+}
 
-    for path in &files {
-        let input = read_to_string(path)?;
-        let todos = find_todos(&conf.keywords, &input);
+/// Files that can't be read are skipped, so one file doesn't abort everything
+fn read_source(conf: &Config, path: &Path, skipped: &mut Vec<Skipped>) -> Option<Source> {
+    match Source::detect(path, &conf.scope(path).read_options) {
+        Ok(source) => Some(source),
+        Err(error) => {
+            skipped.push(Skipped {
+                path: relative(conf, path).to_owned(),
+                error,
+            });
+
+            None
+        }
+    }
+}
 
-        let relative = path.strip_prefix(&conf.root).unwrap();
+/// Reads the todos of the file one at a time
+fn read_todos<'a>(
+    conf: &'a Config,
+    path: &Path,
+    source: &Source,
+) -> io::Result<TodoReader<'a, Box<dyn BufRead>>> {
+    let scope = conf.scope(path);
 
-        todos
-            .into_iter()
-            .filter(|t| {
-                if !unreported && t.issue_id.is_none() {
-                    false
-                } else if !reported && t.issue_id.is_some() {
-                    false
-                } else {
-                    true
-                }
+    Ok(
+        TodoReader::new(&scope.grammar, source.reader(path)?)
+            .max_line_length(scope.max_line_length),
+    )
+}
+
+/// The searched files, relative to the root
+pub fn files(conf: &Config) -> Result<Vec<PathBuf>> {
+    Ok(find_files(&conf.root, &|p| conf.filter(p))?
+        .iter()
+        .map(|p| relative(conf, p).to_owned())
+        .collect())
+}
+
+/// Finds the todos of all searched files
+pub fn scan(conf: &Config) -> Result<Scan> {
+    let files = find_files(&conf.root, &|p| conf.filter(p))?;
+    let mut scan = Scan::default();
+
+    for path in &files {
+        let source = match read_source(conf, path, &mut scan.skipped) {
+            Some(source) => source,
+            None => continue,
+        };
+        let todos = read_todos(conf, path, &source)?.collect::<io::Result<Vec<_>>>()?;
+
+        if !todos.is_empty() {
+            scan.files.push(FileTodos {
+                path: relative(conf, path).to_owned(),
+                todos,
             })
-            .for_each(|t| println!("{}:{}", relative.display(), t));
+        }
     }
 
-    add_to_git();
+    Ok(scan)
+}
 
-    Ok(())
+/// Counts the todos of all searched files, the author and age come from git blame
+pub fn stats(conf: &Config) -> Result<(Stats, Vec<Skipped>)> {
+    let scan = scan(conf)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut stats = Stats::default();
+
+    for file in &scan.files {
+        let blames = blame(&conf.root.join(&file.path));
+
+        for todo in &file.todos {
+            stats.add(&file.path, todo, blames.get(&todo.line), now)
+        }
+    }
+
+    Ok((stats, scan.skipped))
 }
 
-pub async fn report_todos(conf: &Config) -> Result<()> {
-    let files = find_files(&conf.root, &conf.filter_fn).unwrap();
+/// Creates an issue for every new todo and writes its reference into the file.
+/// If an issue can't be created, the file is still marked with the created issues,
+/// but no further files are reported and the update is returned as error.
+pub async fn report(conf: &Config, remote: &mut Remote) -> Result<Update> {
+    let files = find_files(&conf.root, &|p| conf.filter(p))?;
+    let mut update = Update::default();
 
     for path in &files {
-        let input = read_to_string(path)?;
-        let mut todos: Vec<_> = find_todos(&conf.keywords, &input)
-            .into_iter()
-            .filter(|t| t.issue_id.is_none())
-            .collect();
+        let scope = conf.scope(path);
+        let relative = relative(conf, path);
+        let source = match read_source(conf, path, &mut update.skipped) {
+            Some(source) => source,
+            None => continue,
+        };
+        let mut todos = vec![];
+
+        for todo in read_todos(conf, path, &source)? {
+            let todo = todo?;
+
+            match remote.policy(scope, relative, &todo.kind) {
+                Some(policy) if todo.issue_id.is_none() && policy.report => {
+                    todos.push((todo, policy.clone()))
+                }
+                _ => {}
+            }
+        }
+
+        if todos.is_empty() {
+            continue;
+        }
+
+        for (_, policy) in &todos {
+            remote.connect_target(scope, &policy.target).await?;
+        }
+
+        let remote = &*remote;
+
+        let results = join_all(todos.iter_mut().map(|(t, p)| report_todo(remote, p, t))).await;
+        let mut reported = vec![];
+
+        for ((todo, _), result) in todos.into_iter().zip(results) {
+            match result {
+                Ok(()) => reported.push(todo),
+                Err(error) => update.failed.push(Failed {
+                    path: relative.to_owned(),
+                    todo,
+                    error,
+                }),
+            }
+        }
 
-        conf.api.report_todos(&mut todos).await?;
+        let todos = reported;
+        let mut changed = vec![];
 
-        let out = mark_todos(&input, &todos);
+        source.rewrite(path, |input, output| {
+            changed = mark_todos(&scope.grammar, input, output, &todos)?;
 
-        fs::write(path, out.as_bytes())?
+            Ok(!changed.is_empty())
+        })?;
+
+        update.add(relative, &todos, &changed);
+        update.unmarked.extend(
+            todos
+                .into_iter()
+                .filter(|t| !changed.contains(&t.line))
+                .map(|t| (relative.to_owned(), t)),
+        );
+
+        if !update.failed.is_empty() {
+            return Err(TodoError::Report(Box::new(update)));
+        }
     }
 
-    add_to_git();
+    Ok(update)
+}
+
+/// Reports the todo and gives it an issue_id
+async fn report_todo(
+    remote: &Remote,
+    policy: &Policy,
+    todo: &mut Todo,
+) -> std::result::Result<(), ApiError> {
+    let issue = policy.issue(todo);
+
+    todo.issue_id = Some(remote.api(&policy.target).create_issue(&issue).await?);
+    todo.issue_repo = policy.route.clone();
+
     Ok(())
 }
 
-pub async fn purge_todos(conf: &Config) -> Result<()> {
-    let files = find_files(&conf.root, &conf.filter_fn).unwrap();
-    let closed = conf.api.closed_ids().await?;
+/// Removes the todos whose issue is closed
+pub async fn purge(conf: &Config, remote: &mut Remote) -> Result<Update> {
+    let files = find_files(&conf.root, &|p| conf.filter(p))?;
+    let mut update = Update::default();
+    let mut found = vec![];
 
     for path in &files {
-        let input = read_to_string(path)?;
-        let todos: Vec<_> = find_todos(&conf.keywords, &input)
+        let scope = conf.scope(path);
+        let source = match read_source(conf, path, &mut update.skipped) {
+            Some(source) => source,
+            None => continue,
+        };
+        let mut todos = vec![];
+
+        for todo in read_todos(conf, path, &source)? {
+            let todo = todo?;
+
+            if todo.issue_id.is_none() {
+                continue;
+            }
+
+            match remote.issue_target(scope, &todo) {
+                Ok(Some(target)) => todos.push((todo, target)),
+                Ok(None) => {}
+                Err(error) => update.unchecked.push(Unchecked {
+                    path: relative(conf, path).to_owned(),
+                    todo,
+                    reason: error.to_string(),
+                }),
+            }
+        }
+
+        found.push((path, source, todos))
+    }
+
+    let mut referenced: HashMap<_, (&Scope, BTreeSet<_>)> = HashMap::new();
+
+    for (path, _, todos) in &found {
+        for (t, target) in todos {
+            referenced
+                .entry(target.clone())
+                .or_insert_with(|| (conf.scope(path), BTreeSet::new()))
+                .1
+                .extend(t.issue_id.clone());
+        }
+    }
+
+    // Only the referenced issues are looked up, not every issue of the repo
+    let mut closed = HashSet::new();
+    // One repo that can't be reached doesn't stop the others
+    let mut failed = HashMap::new();
+
+    for (target, (scope, ids)) in referenced {
+        let ids: Vec<_> = ids.into_iter().collect();
+
+        match issue_states(remote, scope, &target, &ids).await {
+            Ok(states) => closed.extend(
+                states
+                    .into_iter()
+                    .filter(|(_, state)| state.is_done())
+                    .map(|(id, _)| (target.clone(), id)),
+            ),
+            Err(error) => {
+                failed.insert(target, error.to_string());
+            }
+        }
+    }
+
+    for (path, source, todos) in found {
+        let scope = conf.scope(path);
+        let (todos, unchecked): (Vec<_>, Vec<_>) = todos
             .into_iter()
-            .filter(|t| {
-                if let Some(issue) = t.issue_id {
-                    closed.contains(&issue)
-                } else {
-                    false
-                }
+            .partition(|(_, target)| !failed.contains_key(target));
+
+        update
+            .unchecked
+            .extend(unchecked.into_iter().map(|(todo, target)| Unchecked {
+                path: relative(conf, path).to_owned(),
+                todo,
+                reason: failed[&target].clone(),
+            }));
+
+        let todos: Vec<_> = todos
+            .into_iter()
+            .filter(|(t, target)| match &t.issue_id {
+                Some(issue) => closed.contains(&(target.clone(), issue.clone())),
+                None => false,
             })
+            .map(|(t, _)| t)
             .collect();
 
-        let out = remove_todos(&input, &todos);
+        if todos.is_empty() {
+            continue;
+        }
+
+        let mut changed = vec![];
+
+        source.rewrite(path, |input, output| {
+            changed = remove_todos(&scope.grammar, input, output, &todos)?;
 
-        fs::write(path, out.as_bytes())?
+            Ok(!changed.is_empty())
+        })?;
+
+        update.add(relative(conf, path), &todos, &changed);
     }
 
-    add_to_git();
-    Ok(())
+    Ok(update)
+}
+
+/// Todos can reference repos that aren't configured, so they're connected on demand
+async fn issue_states(
+    remote: &mut Remote,
+    scope: &Scope,
+    target: &Target,
+    ids: &[IssueId],
+) -> Result<HashMap<IssueId, IssueState>> {
+    remote.connect_target(scope, target).await?;
+
+    Ok(remote.api(target).issue_states(ids).await?)
 }
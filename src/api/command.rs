@@ -0,0 +1,207 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    sync::Arc,
+    thread,
+};
+
+use async_trait::async_trait;
+use subprocess::{Exec, ExitStatus, PopenError, Redirection};
+use tokio::task::spawn_blocking;
+
+use super::{scheduler::Scheduler, Api, ApiError, Issue, IssueState, Label};
+use crate::todo::IssueId;
+
+/// Reports to any tracker through shell commands
+pub struct Command {
+    /// Gets the issue as JSON on stdin and prints the id of the created issue
+    create: String,
+    /// Gets the issue ids on stdin, one per line, and prints the closed ones.
+    /// Without it no todo is purged.
+    closed: Option<String>,
+    /// Passed to the commands as `TODO_TOKEN`
+    token: Option<String>,
+    scheduler: Arc<Scheduler>,
+}
+
+#[async_trait]
+impl Api for Command {
+    async fn issue_states(
+        &self,
+        ids: &[IssueId],
+    ) -> Result<HashMap<IssueId, IssueState>, ApiError> {
+        let command = match self.closed {
+            Some(ref command) => command,
+            None => return Ok(HashMap::new()),
+        };
+
+        let input: String = ids.iter().map(|id| format!("{}\n", id)).collect();
+        let output = self.run(command, input).await?;
+        let closed: HashSet<_> = output.lines().filter_map(IssueId::parse).collect();
+
+        Ok(ids
+            .iter()
+            .map(|id| {
+                let state = if closed.contains(id) {
+                    IssueState::Closed
+                } else {
+                    IssueState::Open
+                };
+
+                (id.clone(), state)
+            })
+            .collect())
+    }
+
+    async fn create_issue(&self, issue: &Issue) -> Result<IssueId, ApiError> {
+        // An issue only has strings, so it can always be serialized
+        let input = serde_json::to_string(issue).unwrap();
+        let output = self.run(&self.create, input).await?;
+
+        IssueId::parse(&output).ok_or_else(|| {
+            ApiError::Command(format!(
+                "\"{}\" printed no issue number or key: {}",
+                self.create,
+                output.trim()
+            ))
+        })
+    }
+
+    /// The tracker behind the command has to create missing labels itself
+    async fn create_labels(&mut self, _labels: &[Label]) -> Result<(), ApiError> {
+        Ok(())
+    }
+}
+
+impl Command {
+    pub fn new(
+        create: String,
+        closed: Option<String>,
+        token: Option<String>,
+        scheduler: Arc<Scheduler>,
+    ) -> Command {
+        Command {
+            create,
+            closed,
+            token,
+            scheduler,
+        }
+    }
+
+    /// Commands count as requests, so at most `concurrency` run at the same time
+    async fn run(&self, command: &str, input: String) -> Result<String, ApiError> {
+        let _permit = self.scheduler.permit().await;
+        let shell = command.to_owned();
+        let token = self.token.clone();
+
+        let (status, output) = spawn_blocking(move || run_shell(&shell, input, token))
+            .await
+            .map_err(|e| ApiError::Command(e.to_string()))?
+            .map_err(|e| ApiError::Command(format!("Can't run \"{}\": {}", command, e)))?;
+
+        if status.success() {
+            Ok(output)
+        } else {
+            Err(ApiError::Command(format!("\"{}\" failed", command)))
+        }
+    }
+}
+
+/// The input is written by another thread, so the command can print while it reads.
+/// A command that exits without reading its input isn't an error.
+fn run_shell(
+    command: &str,
+    input: String,
+    token: Option<String>,
+) -> Result<(ExitStatus, String), PopenError> {
+    let mut exec = Exec::shell(command)
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe);
+
+    if let Some(token) = token {
+        exec = exec.env("TODO_TOKEN", token);
+    }
+
+    let mut process = exec.popen()?;
+    let stdin = process.stdin.take();
+    let writer = thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(input.as_bytes());
+        }
+    });
+
+    let mut output = String::new();
+
+    if let Some(mut stdout) = process.stdout.take() {
+        stdout.read_to_string(&mut output)?;
+    }
+
+    let status = process.wait()?;
+    let _ = writer.join();
+
+    Ok((status, output))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn command(create: &str, closed: Option<&str>) -> Command {
+        Command::new(
+            create.to_owned(),
+            closed.map(String::from),
+            Some("secret".to_owned()),
+            Arc::new(Scheduler::new(1, 0)),
+        )
+    }
+
+    #[tokio::test]
+    async fn create_from_output() {
+        let api = command(
+            r#"grep -q '"title":"Something"' && echo "$TODO_TOKEN-12" | tr a-z A-Z"#,
+            None,
+        );
+        let issue = Issue {
+            title: "Something".to_owned(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            IssueId::new("SECRET-12"),
+            api.create_issue(&issue).await.unwrap()
+        )
+    }
+
+    #[tokio::test]
+    async fn create_fails() {
+        let issue = Issue::default();
+
+        assert!(matches!(
+            command("exit 1", None).create_issue(&issue).await,
+            Err(ApiError::Command(m)) if m == "\"exit 1\" failed"
+        ));
+        assert!(matches!(
+            command("echo created", None).create_issue(&issue).await,
+            Err(ApiError::Command(_))
+        ))
+    }
+
+    #[tokio::test]
+    async fn closed_from_output() {
+        let api = command("false", Some("grep -v 7"));
+        let states = api
+            .issue_states(&[5.into(), 7.into(), IssueId::new("PROJ-3")])
+            .await
+            .unwrap();
+
+        assert_eq!(Some(&IssueState::Closed), states.get(&5.into()));
+        assert_eq!(Some(&IssueState::Open), states.get(&7.into()));
+        assert_eq!(
+            Some(&IssueState::Closed),
+            states.get(&IssueId::new("PROJ-3"))
+        );
+
+        let unchecked = command("false", None).issue_states(&[5.into()]).await;
+        assert!(unchecked.unwrap().is_empty())
+    }
+}
@@ -1,71 +1,109 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
+use futures::future::try_join_all;
 use reqwest::{
     header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION},
-    Client,
+    Client, RequestBuilder, StatusCode,
 };
 use serde::Serialize;
 use serde_json::{json, Value};
 use thiserror::Error;
 
-use super::{create_comment_string, Api, ApiError};
-use crate::todo::Todo;
+use super::{check_status, scheduler::Scheduler, Api, ApiError, Issue, IssueState, Label};
+use crate::todo::IssueId;
 
 #[derive(Debug, Error)]
 pub enum GiteaError {
-    #[error(transparent)]
-    Request(#[from] reqwest::Error),
-
     #[error("{0}")]
     Parse(String),
 }
 
 pub struct Gitea {
     issues_url: String,
+    labels_url: String,
     labels: HashMap<String, u64>,
+    milestones: HashMap<String, u64>,
     token: String,
     client: Client,
+    scheduler: Arc<Scheduler>,
 }
 
 #[async_trait]
 impl Api for Gitea {
-    async fn closed_ids(&self) -> Result<Vec<u32>, ApiError> {
-        let mut page = 1;
-        let mut output = Vec::new();
+    async fn issue_states(
+        &self,
+        ids: &[IssueId],
+    ) -> Result<HashMap<IssueId, IssueState>, ApiError> {
+        // Keys of other trackers can't exist on Gitea
+        let numbers: Vec<_> = ids
+            .iter()
+            .filter_map(|id| Some((id, id.number()?)))
+            .collect();
+        // Gitea can't filter by number, the scheduler limits how many are sent at once
+        let issues = try_join_all(numbers.iter().map(|(_, n)| self.get_issue(*n))).await?;
+
+        // Keyed by the ids that were asked for, so `#007` finds the state of issue 7
+        Ok(numbers
+            .into_iter()
+            .zip(issues)
+            .filter_map(|((id, _), issue)| Some((id.clone(), parse_state(issue?).ok()?)))
+            .collect())
+    }
 
-        loop {
-            let json = self
-                .get_issues(&[("state", "closed"), ("page", &format!("{}", page))])
-                .await?;
-            let mut numbers = parse_numbers(json)?;
+    async fn create_issue(&self, issue: &Issue) -> Result<IssueId, ApiError> {
+        let mut json: HashMap<&str, Value> = HashMap::new();
 
-            if !numbers.is_empty() {
-                page += 1;
+        json.insert("title", json!(issue.title.as_str()));
+        json.insert("body", json!(issue.body.as_str()));
 
-                output.append(&mut numbers)
-            } else {
-                break;
-            }
-        }
+        let labels: Vec<_> = issue
+            .labels
+            .iter()
+            .filter_map(|l| self.labels.get(l))
+            .collect();
 
-        Ok(output)
-    }
+        if !labels.is_empty() {
+            json.insert("labels", json!(labels));
+        }
 
-    async fn report_todo(&self, todo: &mut Todo) -> Result<(), ApiError> {
-        let mut json: HashMap<&str, Value> = HashMap::new();
-        let comment_str = create_comment_string(todo);
+        if let Some(ref milestone) = issue.milestone {
+            let id = self
+                .milestones
+                .get(milestone)
+                .ok_or_else(|| ApiError::UnknownMilestone(milestone.clone()))?;
 
-        json.insert("title", json!(todo.title.as_str()));
-        json.insert("body", json!(&comment_str));
+            json.insert("milestone", json!(id));
+        }
 
-        if let Some(id) = self.labels.get(&todo.keyword) {
-            json.insert("labels", json!(&[id]));
+        if !issue.assignees.is_empty() {
+            json.insert("assignees", json!(issue.assignees));
         }
 
         let response = self.post_todo(&json).await?;
 
-        todo.issue_id = Some(parse_issue(response)?);
+        Ok(parse_issue(response)?.into())
+    }
+
+    async fn create_labels(&mut self, labels: &[Label]) -> Result<(), ApiError> {
+        for label in labels {
+            if !self.labels.contains_key(&label.name) {
+                let request = self
+                    .client
+                    .post(&self.labels_url)
+                    .headers(create_header(&self.token)?)
+                    .json(&json!({
+                        "name": label.name,
+                        "color": format!("#{}", label.color),
+                        "description": label.description,
+                    }));
+                let response = send(&self.scheduler, request).await?;
+
+                let mut created = parse_labels(vec![response])?;
+
+                self.labels.extend(created.drain());
+            }
+        }
 
         Ok(())
     }
@@ -77,68 +115,88 @@ impl Gitea {
         token: String,
         user: &str,
         repo: &str,
+        scheduler: Arc<Scheduler>,
     ) -> Result<Gitea, ApiError> {
-        let label_url = format!("{}/repos/{}/{}/labels", base_url, user, repo);
+        let repo_url = format!("{}/repos/{}/{}", base_url, user, repo);
         let client = Client::new();
 
+        let labels_url = format!("{}/labels", repo_url);
+        let labels = get_all(&client, &scheduler, &labels_url, &token).await?;
+        let milestones = get_all(
+            &client,
+            &scheduler,
+            &format!("{}/milestones?state=all", repo_url),
+            &token,
+        )
+        .await?;
+
         Ok(Gitea {
-            issues_url: format!("{}/repos/{}/{}/issues", base_url, user, repo),
-            labels: get_labels(&client, &label_url, &token).await?,
+            issues_url: format!("{}/issues", repo_url),
+            labels_url,
+            labels: parse_labels(labels)?,
+            milestones: parse_milestones(milestones)?,
             token,
             client,
+            scheduler,
         })
     }
 
-    async fn get_issues<T>(&self, query: &T) -> Result<Value, GiteaError>
-    where
-        T: Serialize + ?Sized,
-    {
-        self.client
-            .get(&self.issues_url)
-            .headers(create_header(&self.token))
-            .query(query)
-            .send()
-            .await?
-            .json::<Value>()
-            .await
-            .map_err(|err| GiteaError::Request(err))
+    /// Issues that don't exist are none
+    async fn get_issue(&self, id: u32) -> Result<Option<Value>, ApiError> {
+        let request = self
+            .client
+            .get(&format!("{}/{}", self.issues_url, id))
+            .headers(create_header(&self.token)?);
+        let response = self.scheduler.send(request).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(Some(check_status(response).await?.json::<Value>().await?))
     }
 
-    async fn post_todo<T>(&self, todo: &T) -> Result<Value, GiteaError>
+    async fn post_todo<T>(&self, todo: &T) -> Result<Value, ApiError>
     where
         T: Serialize + ?Sized,
     {
-        self.client
+        let request = self
+            .client
             .post(&self.issues_url)
-            .headers(create_header(&self.token))
-            .json(todo)
-            .send()
-            .await?
-            .json::<Value>()
-            .await
-            .map_err(|err| GiteaError::Request(err))
+            .headers(create_header(&self.token)?)
+            .json(todo);
+
+        send(&self.scheduler, request).await
     }
 }
 
-fn create_header(token: &str) -> HeaderMap {
+fn create_header(token: &str) -> Result<HeaderMap, ApiError> {
     let mut headers = HeaderMap::new();
 
     headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-    headers.insert(AUTHORIZATION, format!("token {}", token).parse().unwrap());
+    headers.insert(AUTHORIZATION, format!("token {}", token).parse()?);
 
-    headers
+    Ok(headers)
 }
 
-async fn get_labels(
+async fn send(scheduler: &Scheduler, request: RequestBuilder) -> Result<Value, ApiError> {
+    let response = check_status(scheduler.send(request).await?).await?;
+
+    Ok(response.json::<Value>().await?)
+}
+
+/// Collects every page of a list endpoint
+async fn get_all(
     client: &Client,
+    scheduler: &Scheduler,
     url: &str,
     token: &str,
-) -> Result<HashMap<String, u64>, GiteaError> {
+) -> Result<Vec<Value>, ApiError> {
     let mut page = 1;
     let mut out = Vec::new();
 
     loop {
-        let mut json = get_labels_raw(client, url, token, page).await?;
+        let mut json = get_page(client, scheduler, url, token, page).await?;
 
         if let Some(arr) = json.as_array_mut() {
             if arr.is_empty() {
@@ -152,34 +210,30 @@ async fn get_labels(
         }
     }
 
-    Ok(parse_labels(out)?)
+    Ok(out)
 }
 
-async fn get_labels_raw(
+async fn get_page(
     client: &Client,
+    scheduler: &Scheduler,
     url: &str,
     token: &str,
     page: i32,
-) -> Result<Value, GiteaError> {
-    client
+) -> Result<Value, ApiError> {
+    let request = client
         .get(url)
-        .headers(create_header(token))
-        .query(&[("page", page)])
-        .send()
-        .await?
-        .json::<Value>()
-        .await
-        .map_err(|e| GiteaError::Request(e))
+        .headers(create_header(token)?)
+        .query(&[("page", page)]);
+
+    send(scheduler, request).await
 }
 
 fn parse_issue(val: Value) -> Result<u32, GiteaError> {
     val.as_object()
         .and_then(|o| o.get("number"))
         .and_then(|v| v.as_u64())
-        .and_then(|u| Some(u as u32))
-        .ok_or(GiteaError::Parse(
-            "Cant't parse requested Issue!".to_owned(),
-        ))
+        .map(|u| u as u32)
+        .ok_or_else(|| GiteaError::Parse("Cant't parse requested Issue!".to_owned()))
 }
 
 fn parse_labels(val: Vec<Value>) -> Result<HashMap<String, u64>, GiteaError> {
@@ -190,30 +244,50 @@ fn parse_labels(val: Vec<Value>) -> Result<HashMap<String, u64>, GiteaError> {
                 .and_then(|t| Some((t.0.as_str()?.to_owned(), t.1.as_u64()?)))
         })
         .collect::<Option<_>>()
-        .ok_or(GiteaError::Parse("Can't parse labels!".to_owned()))
+        .ok_or_else(|| GiteaError::Parse("Can't parse labels!".to_owned()))
 }
 
-fn parse_numbers(val: Value) -> Result<Vec<u32>, GiteaError> {
-    val.as_array()
-        .and_then(|a| {
-            a.into_iter()
-                .map(|v| {
-                    {
-                        v.as_object()
-                            .and_then(|o| o.get("number"))
-                            .and_then(|v| v.as_u64())
-                            .and_then(|u| Some(u as u32))
-                    }
-                })
-                .collect::<Option<_>>()
+fn parse_milestones(val: Vec<Value>) -> Result<HashMap<String, u64>, GiteaError> {
+    val.into_iter()
+        .map(|v| {
+            v.as_object()
+                .and_then(|o| Some((o.get("title")?, o.get("id")?)))
+                .and_then(|t| Some((t.0.as_str()?.to_owned(), t.1.as_u64()?)))
         })
-        .ok_or(GiteaError::Parse("Can't parse closed id's".to_owned()))
+        .collect::<Option<_>>()
+        .ok_or_else(|| GiteaError::Parse("Can't parse milestones!".to_owned()))
+}
+
+/// Pull requests share their numbers with issues and are returned by the issue endpoint
+fn parse_state(val: Value) -> Result<IssueState, GiteaError> {
+    let pull = val.get("pull_request").filter(|p| !p.is_null());
+    let merged = pull
+        .and_then(|p| p.get("merged"))
+        .and_then(|m| m.as_bool())
+        .unwrap_or(false);
+
+    match (val.get("state").and_then(|s| s.as_str()), pull) {
+        (Some("open"), _) => Ok(IssueState::Open),
+        (Some("closed"), None) => Ok(IssueState::Closed),
+        (Some("closed"), Some(_)) if merged => Ok(IssueState::Merged),
+        (Some("closed"), Some(_)) => Ok(IssueState::Declined),
+        _ => Err(GiteaError::Parse("Can't parse issue state".to_owned())),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn invalid_token_header() {
+        assert!(create_header("secret").is_ok());
+        assert!(matches!(
+            create_header("sec\nret"),
+            Err(ApiError::Header(_))
+        ))
+    }
+
     #[test]
     fn parse_issue_success() {
         let val = json!({
@@ -223,7 +297,7 @@ mod tests {
         if let Ok(issue) = parse_issue(val) {
             assert_eq!(123, issue)
         } else {
-            assert!(false)
+            panic!()
         }
     }
 
@@ -236,7 +310,7 @@ mod tests {
         if let Err(GiteaError::Parse(issue)) = parse_issue(val) {
             assert_eq!("Cant't parse requested Issue!".to_owned(), issue)
         } else {
-            assert!(false)
+            panic!()
         }
     }
 
@@ -257,7 +331,7 @@ mod tests {
             assert_eq!(&123, map.get("123").unwrap());
             assert_eq!(&456, map.get("456").unwrap())
         } else {
-            assert!(false)
+            panic!()
         }
     }
 
@@ -276,43 +350,63 @@ mod tests {
         if let Err(GiteaError::Parse(issue)) = parse_labels(val) {
             assert_eq!("Can't parse labels!".to_owned(), issue)
         } else {
-            assert!(false)
+            panic!()
         }
     }
 
     #[test]
-    fn parse_numbers_success() {
-        let val = json!([
-            {
-                "number": 123,
-            },
-            {
-                "number": 456,
-            }
-        ]);
+    fn parse_milestones_success() {
+        let val = vec![json!({
+            "title": "v1.0",
+            "id": 3
+        })];
+
+        if let Ok(map) = parse_milestones(val) {
+            assert_eq!(&3, map.get("v1.0").unwrap())
+        } else {
+            panic!()
+        }
+    }
 
-        if let Ok(numbers) = parse_numbers(val) {
-            assert_eq!(vec![123, 456], numbers);
+    #[test]
+    fn parse_state_success() {
+        let val = json!({
+            "number": 123,
+            "state": "closed"
+        });
+
+        if let Ok(state) = parse_state(val) {
+            assert_eq!(IssueState::Closed, state);
         } else {
-            assert!(false)
+            panic!()
         }
     }
 
     #[test]
-    fn parse_numbers_fail() {
-        let val = json!([
-            {
-                "name": "123",
-            },
-            {
-                "name": "456",
-            }
-        ]);
+    fn parse_state_pull_request() {
+        let merged = json!({
+            "state": "closed",
+            "pull_request": { "merged": true }
+        });
+        let declined = json!({
+            "state": "closed",
+            "pull_request": { "merged": false }
+        });
+
+        assert_eq!(IssueState::Merged, parse_state(merged).unwrap());
+        assert_eq!(IssueState::Declined, parse_state(declined).unwrap())
+    }
+
+    #[test]
+    fn parse_state_fail() {
+        let val = json!({
+            "number": 123,
+        });
 
-        if let Err(GiteaError::Parse(issue)) = parse_numbers(val) {
-            assert_eq!("Can't parse closed id's".to_owned(), issue)
+        if let Err(GiteaError::Parse(issue)) = parse_state(val) {
+            assert_eq!("Can't parse issue state".to_owned(), issue)
         } else {
-            assert!(false)
+            panic!()
         }
     }
 }
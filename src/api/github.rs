@@ -1,113 +1,387 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
-use octocrab::{params, Octocrab};
+use octocrab::{
+    models::{issues, Label as GithubLabel, Milestone},
+    Octocrab,
+};
+use reqwest::{header::HeaderValue, Method};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
 
-use super::{create_comment_string, Api, ApiError};
-use crate::todo::Todo;
+use super::{check_status, scheduler::Scheduler, Api, ApiError, Issue, IssueState, Label};
+use crate::todo::IssueId;
+
+/// Issues looked up with one GraphQL query
+const GRAPHQL_BATCH: usize = 100;
+
+const STATE_FIELDS: &str = "__typename ... on Issue { state } ... on PullRequest { state }";
 
 pub struct Github {
     user: String,
     repo: String,
     client: Octocrab,
+    scheduler: Arc<Scheduler>,
     labels: HashSet<String>,
+    milestones: HashMap<String, u64>,
 }
 
 #[async_trait]
 impl Api for Github {
-    async fn closed_ids(&self) -> Result<Vec<u32>, ApiError> {
-        let mut page: u32 = 1;
-        let mut output = Vec::new();
+    async fn issue_states(
+        &self,
+        ids: &[IssueId],
+    ) -> Result<HashMap<IssueId, IssueState>, ApiError> {
+        let mut states = HashMap::new();
 
-        loop {
-            let issues = self
-                .client
-                .issues(&self.user, &self.repo)
-                .list()
-                .state(params::State::Closed)
-                .per_page(100)
-                .page(page)
-                .send()
-                .await?
-                .items;
-
-            if issues.is_empty() {
-                break;
-            } else {
-                let mut numbers = issues.into_iter().map(|i| i.number as u32).collect();
+        for batch in numbers(ids).chunks(GRAPHQL_BATCH) {
+            let body = json!({
+                "query": states_query(batch),
+                "variables": { "owner": self.user, "name": self.repo },
+            });
+            let response: Value = self.query(&body).await?;
 
-                output.append(&mut numbers);
-                page += 1
-            }
+            states.extend(parse_states(&response)?);
         }
 
-        Ok(output)
+        Ok(by_id(ids, &states))
     }
 
-    async fn report_todo(&self, todo: &mut Todo) -> Result<(), ApiError> {
-        // Create variable otherwise the IssueHandler is dropped
-        let handler = self.client.issues(&self.user, &self.repo);
+    async fn create_issue(&self, issue: &Issue) -> Result<IssueId, ApiError> {
+        let mut json = Map::new();
+
+        json.insert("title".to_owned(), json!(issue.title));
+
+        if !issue.body.is_empty() {
+            json.insert("body".to_owned(), json!(issue.body));
+        }
+
+        let labels: Vec<_> = issue
+            .labels
+            .iter()
+            .filter(|l| self.labels.contains(*l))
+            .collect();
 
-        let mut builder = handler.create(&todo.title);
+        if !labels.is_empty() {
+            json.insert("labels".to_owned(), json!(labels));
+        }
+
+        if let Some(ref milestone) = issue.milestone {
+            let number = self
+                .milestones
+                .get(milestone)
+                .ok_or_else(|| ApiError::UnknownMilestone(milestone.clone()))?;
 
-        if !todo.comments.is_empty() {
-            builder = builder.body(create_comment_string(&todo))
+            json.insert("milestone".to_owned(), json!(number));
         }
 
-        if self.labels.contains(&todo.keyword) {
-            builder = builder.labels(vec![todo.keyword.clone()])
+        if !issue.assignees.is_empty() {
+            json.insert("assignees".to_owned(), json!(issue.assignees));
         }
 
-        let result = builder.send().await?;
+        let result: issues::Issue = self.post(&self.route("issues"), &json).await?;
 
-        todo.issue_id = Some(result.number as u32);
+        Ok(IssueId::from(result.number as u32))
+    }
+
+    async fn create_labels(&mut self, labels: &[Label]) -> Result<(), ApiError> {
+        for label in labels {
+            if !self.labels.contains(&label.name) {
+                let _: Value = self
+                    .post(
+                        &self.route("labels"),
+                        &json!({
+                            "name": label.name,
+                            "color": label.color,
+                            "description": label.description,
+                        }),
+                    )
+                    .await?;
+
+                self.labels.insert(label.name.clone());
+            }
+        }
 
         Ok(())
     }
 }
 
 impl Github {
-    pub async fn new(user: String, repo: String, token: String) -> Result<Github, ApiError> {
-        let client = Octocrab::builder().personal_token(token).build()?;
-        let labels = get_labels(&user, &repo, &client).await?;
+    pub async fn new(
+        user: String,
+        repo: String,
+        token: String,
+        scheduler: Arc<Scheduler>,
+    ) -> Result<Github, ApiError> {
+        // The builder panics on a token that can't be sent in a header
+        format!("Bearer {}", token).parse::<HeaderValue>()?;
 
-        Ok(Github {
+        let client = Octocrab::builder().personal_token(token).build()?;
+        let mut github = Github {
             user,
             repo,
             client,
-            labels,
-        })
+            scheduler,
+            labels: HashSet::new(),
+            milestones: HashMap::new(),
+        };
+
+        github.labels = github.get_labels().await?;
+        github.milestones = github.get_milestones().await?;
+
+        Ok(github)
     }
-}
 
-async fn get_labels(
-    user: &str,
-    repo: &str,
-    client: &Octocrab,
-) -> Result<HashSet<String>, ApiError> {
-    let mut page: u32 = 1;
-    let mut out = HashSet::new();
-
-    loop {
-        let labels = client
-            .issues(user, repo)
-            .list_labels_for_repo()
-            .per_page(100)
-            .page(page)
-            .send()
-            .await?
-            .items;
+    fn route(&self, path: &str) -> String {
+        format!("/repos/{}/{}/{}", self.user, self.repo, path)
+    }
 
-        if !labels.is_empty() {
-            page += 1;
+    async fn get<T: DeserializeOwned>(
+        &self,
+        route: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, ApiError> {
+        let request = self
+            .client
+            .request_builder(self.client.absolute_url(route)?, Method::GET)
+            .query(query);
+
+        self.send(request).await
+    }
+
+    /// GraphQL queries only read, so they are retried like a GET
+    async fn query<T: DeserializeOwned>(&self, body: &Value) -> Result<T, ApiError> {
+        let request = self
+            .client
+            .request_builder(self.client.absolute_url("/graphql")?, Method::POST)
+            .json(body);
+        let response = self.scheduler.send_idempotent(request).await?;
+
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        route: &str,
+        body: &(impl Serialize + ?Sized + Sync),
+    ) -> Result<T, ApiError> {
+        let request = self
+            .client
+            .request_builder(self.client.absolute_url(route)?, Method::POST)
+            .json(body);
+
+        self.send(request).await
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, ApiError> {
+        let response = self.scheduler.send(request).await?;
+        let response = check_status(response).await?;
+
+        Ok(response.json().await?)
+    }
+
+    async fn get_labels(&self) -> Result<HashSet<String>, ApiError> {
+        let mut page: u32 = 1;
+        let mut out = HashSet::new();
+
+        loop {
+            let labels: Vec<GithubLabel> = self
+                .get(
+                    &self.route("labels"),
+                    &[("per_page", "100".to_owned()), ("page", page.to_string())],
+                )
+                .await?;
+
+            if !labels.is_empty() {
+                page += 1;
+
+                for label in labels {
+                    out.insert(label.name);
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(out)
+    }
+
+    async fn get_milestones(&self) -> Result<HashMap<String, u64>, ApiError> {
+        let mut page: u32 = 1;
+        let mut out = HashMap::new();
+
+        loop {
+            let milestones: Vec<Milestone> = self
+                .get(
+                    &self.route("milestones"),
+                    &[
+                        ("state", "all".to_owned()),
+                        ("per_page", "100".to_owned()),
+                        ("page", page.to_string()),
+                    ],
+                )
+                .await?;
+
+            if !milestones.is_empty() {
+                page += 1;
 
-            for label in labels {
-                out.insert(label.name);
+                for milestone in milestones {
+                    out.insert(milestone.title, milestone.number as u64);
+                }
+            } else {
+                break;
             }
-        } else {
-            break;
         }
+
+        Ok(out)
+    }
+}
+
+/// The numbers that can be looked up, keys of other trackers can't exist on Github.
+/// GraphQL numbers are 32 bit, a larger one would fail the whole query.
+fn numbers(ids: &[IssueId]) -> Vec<u32> {
+    ids.iter()
+        .filter_map(IssueId::number)
+        .filter(|n| *n <= i32::MAX as u32)
+        .collect()
+}
+
+/// Looks up every issue with an alias like `i12`
+fn states_query(ids: &[u32]) -> String {
+    let issues: String = ids
+        .iter()
+        .map(|id| {
+            format!(
+                "i{0}: issueOrPullRequest(number: {0}) {{ {1} }} ",
+                id, STATE_FIELDS
+            )
+        })
+        .collect();
+
+    format!(
+        "query($owner: String!, $name: String!) {{ repository(owner: $owner, name: $name) {{ {}}} }}",
+        issues
+    )
+}
+
+/// Keys the states by the ids that were asked for, so `#007` finds the state of issue 7
+fn by_id(ids: &[IssueId], states: &HashMap<u32, IssueState>) -> HashMap<IssueId, IssueState> {
+    ids.iter()
+        .filter_map(|id| Some((id.clone(), *states.get(&id.number()?)?)))
+        .collect()
+}
+
+/// The states by issue number.
+/// Issues that don't exist are null and have an error, they are skipped
+fn parse_states(response: &Value) -> Result<HashMap<u32, IssueState>, ApiError> {
+    let repository = response
+        .pointer("/data/repository")
+        .and_then(Value::as_object)
+        .ok_or_else(|| {
+            let message = response
+                .pointer("/errors/0/message")
+                .and_then(Value::as_str)
+                .unwrap_or("missing repository");
+
+            ApiError::Graphql(message.to_owned())
+        })?;
+
+    Ok(repository
+        .iter()
+        .filter_map(|(alias, issue)| {
+            let id = alias.strip_prefix('i')?.parse::<u32>().ok()?;
+            let is_pull = issue.get("__typename")?.as_str()? == "PullRequest";
+            let state = match (issue.get("state")?.as_str()?, is_pull) {
+                ("OPEN", _) => IssueState::Open,
+                ("MERGED", _) => IssueState::Merged,
+                (_, true) => IssueState::Declined,
+                (_, false) => IssueState::Closed,
+            };
+
+            Some((id, state))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_aliases() {
+        assert_eq!(
+            format!(
+                "query($owner: String!, $name: String!) {{ repository(owner: $owner, name: $name) {{ \
+                 i1: issueOrPullRequest(number: 1) {{ {0} }} }} }}",
+                STATE_FIELDS
+            ),
+            states_query(&[1])
+        )
+    }
+
+    #[test]
+    fn skip_large_numbers() {
+        let ids = [
+            IssueId::new("1"),
+            IssueId::new("2147483648"),
+            IssueId::new("PROJ-7"),
+        ];
+
+        assert_eq!(vec![1], numbers(&ids))
+    }
+
+    #[test]
+    fn parse_states_skips_missing() {
+        let response = json!({
+            "data": {
+                "repository": {
+                    "i1": { "__typename": "Issue", "state": "OPEN" },
+                    "i2": { "__typename": "Issue", "state": "CLOSED" },
+                    "i3": null,
+                    "i4": { "__typename": "PullRequest", "state": "MERGED" },
+                    "i5": { "__typename": "PullRequest", "state": "CLOSED" }
+                }
+            },
+            "errors": [{ "message": "Could not resolve to an Issue with the number of 3." }]
+        });
+
+        let states = parse_states(&response).unwrap();
+
+        assert_eq!(4, states.len());
+        assert_eq!(Some(&IssueState::Open), states.get(&1));
+        assert_eq!(Some(&IssueState::Closed), states.get(&2));
+        assert_eq!(Some(&IssueState::Merged), states.get(&4));
+        assert_eq!(Some(&IssueState::Declined), states.get(&5))
     }
 
-    Ok(out)
+    #[test]
+    fn states_by_sent_id() {
+        let states = [(7, IssueState::Closed)].iter().cloned().collect();
+        let ids = [IssueId::new("007"), IssueId::new("PROJ-7")];
+
+        assert_eq!(
+            Some(&IssueState::Closed),
+            by_id(&ids, &states).get(&IssueId::new("007"))
+        );
+        assert_eq!(1, by_id(&ids, &states).len())
+    }
+
+    #[test]
+    fn parse_states_missing_repo() {
+        let response = json!({
+            "data": { "repository": null },
+            "errors": [{ "message": "Could not resolve to a Repository" }]
+        });
+
+        assert!(matches!(
+            parse_states(&response),
+            Err(ApiError::Graphql(message)) if message == "Could not resolve to a Repository"
+        ))
+    }
 }
@@ -0,0 +1,398 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use reqwest::{header::ACCEPT, Client, Method, RequestBuilder};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+use super::{check_status, scheduler::Scheduler, Api, ApiError, Issue, IssueState, Label};
+use crate::todo::IssueId;
+
+/// Used when the keyword has no `issue_type`
+const DEFAULT_ISSUE_TYPE: &str = "Task";
+
+/// Issues looked up with one search
+const SEARCH_BATCH: usize = 50;
+
+#[derive(Debug, Error)]
+pub enum JiraError {
+    #[error("{0}")]
+    Parse(String),
+}
+
+/// Jira Cloud and Jira Server (or Data Center) differ in the api version and authentication
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Flavor {
+    /// REST v3 with the account email and an api token, descriptions are Atlassian documents
+    Cloud,
+    /// REST v2 with a personal access token, descriptions are plain text
+    Server,
+}
+
+impl Flavor {
+    /// Jira Cloud is hosted on atlassian.net, every other host is a server
+    pub fn of(url: &str) -> Flavor {
+        let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let host = rest.split(['/', ':']).next().unwrap_or(rest);
+
+        if host.ends_with(".atlassian.net") {
+            Flavor::Cloud
+        } else {
+            Flavor::Server
+        }
+    }
+
+    fn version(self) -> u8 {
+        match self {
+            Flavor::Cloud => 3,
+            Flavor::Server => 2,
+        }
+    }
+}
+
+pub struct Jira {
+    api_url: String,
+    flavor: Flavor,
+    /// Key of the project the issues are created in, like `PROJ`
+    project: String,
+    /// Email of the account, only needed by Jira Cloud
+    user: String,
+    token: String,
+    client: Client,
+    scheduler: Arc<Scheduler>,
+}
+
+#[async_trait]
+impl Api for Jira {
+    async fn issue_states(
+        &self,
+        ids: &[IssueId],
+    ) -> Result<HashMap<IssueId, IssueState>, ApiError> {
+        // Numbers are issues of other trackers
+        let keys: Vec<_> = ids.iter().filter(|id| id.number().is_none()).collect();
+        let batches =
+            try_join_all(keys.chunks(SEARCH_BATCH).map(|batch| self.search(batch))).await?;
+
+        Ok(batches.into_iter().flatten().collect())
+    }
+
+    async fn create_issue(&self, issue: &Issue) -> Result<IssueId, ApiError> {
+        let request = self
+            .request(Method::POST, "issue")
+            .json(&json!({ "fields": self.fields(issue) }));
+        let response = send(&self.scheduler, request).await?;
+
+        Ok(parse_key(&response)?)
+    }
+
+    /// Jira creates labels when they are used the first time
+    async fn create_labels(&mut self, _labels: &[Label]) -> Result<(), ApiError> {
+        Ok(())
+    }
+}
+
+impl Jira {
+    /// Checks that the project exists, so a wrong key or token fails before any todo is reported
+    pub async fn new(
+        url: &str,
+        flavor: Flavor,
+        project: String,
+        user: String,
+        token: String,
+        scheduler: Arc<Scheduler>,
+    ) -> Result<Jira, ApiError> {
+        let jira = Jira {
+            api_url: format!(
+                "{}/rest/api/{}",
+                url.trim_end_matches('/'),
+                flavor.version()
+            ),
+            flavor,
+            project,
+            user,
+            token,
+            client: Client::new(),
+            scheduler,
+        };
+
+        let route = format!("project/{}", jira.project);
+        send(&jira.scheduler, jira.request(Method::GET, &route)).await?;
+
+        Ok(jira)
+    }
+
+    fn request(&self, method: Method, route: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, &format!("{}/{}", self.api_url, route))
+            .header(ACCEPT, "application/json");
+
+        match self.flavor {
+            Flavor::Cloud => request.basic_auth(&self.user, Some(&self.token)),
+            Flavor::Server => request.bearer_auth(&self.token),
+        }
+    }
+
+    /// Keys that don't exist are only a warning of the search, they are skipped
+    async fn search(&self, keys: &[&IssueId]) -> Result<HashMap<IssueId, IssueState>, ApiError> {
+        let keys: Vec<_> = keys.iter().map(|k| format!("\"{}\"", k)).collect();
+        let jql = format!("key in ({})", keys.join(", "));
+        let request = self.request(Method::GET, "search").query(&[
+            ("jql", jql.as_str()),
+            ("fields", "status"),
+            ("validateQuery", "warn"),
+            ("maxResults", &keys.len().to_string()),
+        ]);
+
+        Ok(parse_issues(&send(&self.scheduler, request).await?))
+    }
+
+    fn fields(&self, issue: &Issue) -> Map<String, Value> {
+        let mut fields = Map::new();
+
+        fields.insert("project".to_owned(), json!({ "key": self.project }));
+        fields.insert("summary".to_owned(), json!(issue.title));
+        fields.insert(
+            "issuetype".to_owned(),
+            json!({ "name": issue.issue_type.as_deref().unwrap_or(DEFAULT_ISSUE_TYPE) }),
+        );
+
+        if !issue.body.is_empty() {
+            let description = match self.flavor {
+                Flavor::Cloud => document(&issue.body),
+                Flavor::Server => json!(issue.body),
+            };
+
+            fields.insert("description".to_owned(), description);
+        }
+
+        if !issue.labels.is_empty() {
+            fields.insert("labels".to_owned(), json!(issue.labels));
+        }
+
+        if let Some(ref milestone) = issue.milestone {
+            fields.insert("fixVersions".to_owned(), json!([{ "name": milestone }]));
+        }
+
+        // Jira issues have only one assignee
+        if let Some(assignee) = issue.assignees.first() {
+            let user = match self.flavor {
+                Flavor::Cloud => json!({ "accountId": assignee }),
+                Flavor::Server => json!({ "name": assignee }),
+            };
+
+            fields.insert("assignee".to_owned(), user);
+        }
+
+        fields
+    }
+}
+
+async fn send(scheduler: &Scheduler, request: RequestBuilder) -> Result<Value, ApiError> {
+    let response = check_status(scheduler.send(request).await?).await?;
+
+    Ok(response.json::<Value>().await?)
+}
+
+/// Jira Cloud takes the description as Atlassian document, every line becomes a paragraph
+fn document(text: &str) -> Value {
+    let paragraphs: Vec<_> = text
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| json!({ "type": "paragraph", "content": [{ "type": "text", "text": l }] }))
+        .collect();
+
+    json!({ "type": "doc", "version": 1, "content": paragraphs })
+}
+
+fn parse_key(val: &Value) -> Result<IssueId, JiraError> {
+    val.get("key")
+        .and_then(Value::as_str)
+        .map(IssueId::new)
+        .ok_or_else(|| JiraError::Parse("Can't parse created issue!".to_owned()))
+}
+
+/// The states of the found issues by their key
+fn parse_issues(val: &Value) -> HashMap<IssueId, IssueState> {
+    val.get("issues")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|issue| Some((parse_key(issue).ok()?, parse_state(issue).ok()?)))
+        .collect()
+}
+
+/// Every status belongs to one of the categories `new`, `indeterminate` and `done`
+fn parse_state(val: &Value) -> Result<IssueState, JiraError> {
+    match val
+        .pointer("/fields/status/statusCategory/key")
+        .and_then(Value::as_str)
+    {
+        Some("done") => Ok(IssueState::Closed),
+        Some(_) => Ok(IssueState::Open),
+        None => Err(JiraError::Parse("Can't parse issue state".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::{mock, Matcher};
+
+    use super::*;
+
+    fn status(category: &str) -> Value {
+        json!({ "key": "PROJ-1", "fields": { "status": { "statusCategory": { "key": category } } } })
+    }
+
+    fn jira(flavor: Flavor) -> Jira {
+        Jira {
+            api_url: String::new(),
+            flavor,
+            project: "PROJ".to_owned(),
+            user: "me@example.com".to_owned(),
+            token: "secret".to_owned(),
+            client: Client::new(),
+            scheduler: Arc::new(Scheduler::new(1, 0)),
+        }
+    }
+
+    fn issue() -> Issue {
+        Issue {
+            title: "Something".to_owned(),
+            body: "More\n\nAnd More".to_owned(),
+            assignees: vec!["alice".to_owned(), "bob".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flavor_of_host() {
+        assert_eq!(Flavor::Cloud, Flavor::of("https://team.atlassian.net"));
+        assert_eq!(Flavor::Server, Flavor::of("https://jira.example.com/"));
+        assert_eq!(Flavor::Server, Flavor::of("http://127.0.0.1:8080"))
+    }
+
+    #[test]
+    fn parse_state_categories() {
+        assert_eq!(IssueState::Closed, parse_state(&status("done")).unwrap());
+        assert_eq!(
+            IssueState::Open,
+            parse_state(&status("indeterminate")).unwrap()
+        );
+        assert!(parse_state(&json!({ "key": "PROJ-1" })).is_err())
+    }
+
+    #[test]
+    fn parse_key_success() {
+        let val = json!({ "id": "10000", "key": "PROJ-24" });
+
+        assert_eq!(IssueId::new("PROJ-24"), parse_key(&val).unwrap())
+    }
+
+    #[test]
+    fn server_fields() {
+        let fields = jira(Flavor::Server).fields(&issue());
+
+        assert_eq!(json!("More\n\nAnd More"), fields["description"]);
+        assert_eq!(json!({ "name": "Task" }), fields["issuetype"]);
+        assert_eq!(json!({ "name": "alice" }), fields["assignee"])
+    }
+
+    #[test]
+    fn cloud_fields() {
+        let fields = jira(Flavor::Cloud).fields(&Issue {
+            issue_type: Some("Bug".to_owned()),
+            ..issue()
+        });
+
+        assert_eq!(
+            json!({ "type": "doc", "version": 1, "content": [
+                { "type": "paragraph", "content": [{ "type": "text", "text": "More" }] },
+                { "type": "paragraph", "content": [{ "type": "text", "text": "And More" }] }
+            ] }),
+            fields["description"]
+        );
+        assert_eq!(json!({ "name": "Bug" }), fields["issuetype"]);
+        assert_eq!(json!({ "accountId": "alice" }), fields["assignee"])
+    }
+
+    #[tokio::test]
+    async fn create_and_look_up_issues() {
+        let _project = mock("GET", "/rest/api/2/project/PROJ")
+            .match_header("authorization", "Bearer secret")
+            .with_body(r#"{"key": "PROJ"}"#)
+            .create();
+        let created = mock("POST", "/rest/api/2/issue")
+            .match_body(Matcher::PartialJson(json!({ "fields": {
+                "project": { "key": "PROJ" },
+                "summary": "Something",
+                "issuetype": { "name": "Task" }
+            } })))
+            .with_status(201)
+            .with_body(r#"{"id": "10000", "key": "PROJ-7"}"#)
+            .create();
+        let search = mock("GET", "/rest/api/2/search")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("jql".to_owned(), r#"key in ("PROJ-7", "PROJ-8")"#.to_owned()),
+                Matcher::UrlEncoded("validateQuery".to_owned(), "warn".to_owned()),
+            ]))
+            .with_body(
+                json!({
+                    "issues": [
+                        { "key": "PROJ-7", "fields": { "status": { "statusCategory": { "key": "done" } } } }
+                    ],
+                    "warningMessages": ["The issue key 'PROJ-8' for field 'key' is invalid."]
+                })
+                .to_string(),
+            )
+            .create();
+
+        let jira = Jira::new(
+            &mockito::server_url(),
+            Flavor::Server,
+            "PROJ".to_owned(),
+            "me@example.com".to_owned(),
+            "secret".to_owned(),
+            Arc::new(Scheduler::new(1, 0)),
+        )
+        .await
+        .unwrap();
+
+        let id = jira.create_issue(&issue()).await.unwrap();
+        created.assert();
+        assert_eq!(IssueId::new("PROJ-7"), id);
+
+        let states = jira
+            .issue_states(&[id.clone(), IssueId::new("PROJ-8"), 12.into()])
+            .await
+            .unwrap();
+
+        search.assert();
+        assert_eq!(1, states.len());
+        assert_eq!(Some(&IssueState::Closed), states.get(&id))
+    }
+
+    #[tokio::test]
+    async fn unknown_project() {
+        let _project = mock("GET", "/rest/api/2/project/NOPE")
+            .with_status(404)
+            .with_body(r#"{"errorMessages": ["No project could be found with key 'NOPE'."]}"#)
+            .create();
+
+        let result = Jira::new(
+            &mockito::server_url(),
+            Flavor::Server,
+            "NOPE".to_owned(),
+            "me@example.com".to_owned(),
+            "secret".to_owned(),
+            Arc::new(Scheduler::new(1, 0)),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(ApiError::NotFound(m)) if m == "No project could be found with key 'NOPE'."
+        ))
+    }
+}
@@ -1,12 +1,20 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
-use futures::future::try_join_all;
+use reqwest::{Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
 use thiserror::Error;
 
-use crate::todo::Todo;
+use crate::todo::{IssueId, Todo};
 use gitea::GiteaError;
+use jira::JiraError;
 
+pub mod command;
 pub mod gitea;
 pub mod github;
+pub mod jira;
+pub mod scheduler;
 
 #[derive(Debug, Error)]
 pub enum ApiError {
@@ -15,37 +23,177 @@ pub enum ApiError {
 
     #[error(transparent)]
     Github(#[from] octocrab::Error),
+
+    #[error(transparent)]
+    Jira(#[from] JiraError),
+
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+
+    #[error("Token can't be sent in a header: {0}")]
+    Header(#[from] reqwest::header::InvalidHeaderValue),
+
+    #[error("Milestone {0} doesn't exist!")]
+    UnknownMilestone(String),
+
+    #[error("GraphQL request failed: {0}")]
+    Graphql(String),
+
+    #[error("{0}")]
+    Command(String),
+
+    #[error("Authentication failed, check the token: {0}")]
+    Unauthorized(String),
+
+    #[error("Permission denied, check the scopes of the token: {0}")]
+    Forbidden(String),
+
+    #[error("Not found, check the user and repo: {0}")]
+    NotFound(String),
+
+    #[error("Invalid request: {0}")]
+    Invalid(String),
+
+    #[error("Request failed with status {0}: {1}")]
+    Status(u16, String),
 }
 
-#[async_trait]
-pub trait Api: Sync {
-    async fn closed_ids(&self) -> Result<Vec<u32>, ApiError>;
-
-    /// Reports the todo and gives it an issue_id
-    async fn report_todo(&self, todo: &mut Todo) -> Result<(), ApiError>;
-
-    async fn report_todos(&self, todos: &mut [Todo]) -> Result<(), ApiError> {
-        try_join_all(
-            todos
-                .into_iter()
-                .map(|t| self.report_todo(t))
-                .collect::<Vec<_>>(),
-        )
-        .await?;
-        Ok(())
+/// Turns an unsuccessful response into an error with the message of the server
+pub async fn check_status(response: Response) -> Result<Response, ApiError> {
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+
+    Err(status_error(status, &body))
+}
+
+fn status_error(status: StatusCode, body: &str) -> ApiError {
+    let message = error_message(body).unwrap_or_else(|| match body.trim() {
+        "" => status.canonical_reason().unwrap_or_default().to_owned(),
+        body => body.to_owned(),
+    });
+
+    match status {
+        StatusCode::UNAUTHORIZED => ApiError::Unauthorized(message),
+        StatusCode::FORBIDDEN => ApiError::Forbidden(message),
+        StatusCode::NOT_FOUND => ApiError::NotFound(message),
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => ApiError::Invalid(message),
+        status => ApiError::Status(status.as_u16(), message),
+    }
+}
+
+/// Github and Gitea both send a `message`, Github adds the failed fields in `errors`.
+/// Jira sends `errorMessages` and the failed fields as a map in `errors`.
+fn error_message(body: &str) -> Option<String> {
+    let json: Value = serde_json::from_str(body).ok()?;
+    let messages: Vec<_> = match json.get("message") {
+        Some(message) => vec![message.as_str()?],
+        None => json
+            .get("errorMessages")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+            .collect(),
+    };
+
+    let details: Vec<_> = match json.get("errors") {
+        Some(Value::Array(errors)) => errors
+            .iter()
+            .filter_map(|e| match e {
+                Value::String(s) => Some(s.clone()),
+                e => Some(format!(
+                    "{} {}",
+                    e.get("field")?.as_str()?,
+                    e.get("code")?.as_str()?
+                )),
+            })
+            .collect(),
+        Some(Value::Object(fields)) => fields
+            .iter()
+            .filter_map(|(field, e)| Some(format!("{}: {}", field, e.as_str()?)))
+            .collect(),
+        _ => vec![],
+    };
+
+    let mut message = messages.join(" ");
+
+    if message.is_empty() {
+        message = details.join(", ");
+    } else if !details.is_empty() {
+        message.push_str(&format!(" ({})", details.join(", ")));
+    }
+
+    Some(message).filter(|m| !m.is_empty())
+}
+
+/// An issue that will be created from a todo
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct Issue {
+    pub title: String,
+    pub body: String,
+    /// Labels that don't exist in the repo are skipped
+    pub labels: Vec<String>,
+    pub milestone: Option<String>,
+    pub assignees: Vec<String>,
+    /// Only used by Jira, which defaults to `Task`
+    pub issue_type: Option<String>,
+}
+
+/// A label that will be created if it doesn't exist
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label {
+    pub name: String,
+    /// Hex color without the leading #
+    pub color: String,
+    pub description: String,
+}
+
+/// Whether the issue or pull request is still worked on
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IssueState {
+    Open,
+    Closed,
+    /// A pull request that was merged
+    Merged,
+    /// A pull request that was closed without merging
+    Declined,
+}
+
+impl IssueState {
+    /// Todos of closed issues and merged pull requests are purged,
+    /// the work of a declined pull request is still to be done
+    pub fn is_done(self) -> bool {
+        matches!(self, IssueState::Closed | IssueState::Merged)
     }
 }
 
+#[async_trait]
+pub trait Api: Send + Sync {
+    /// The states of the issues, issues that don't exist are missing
+    async fn issue_states(&self, ids: &[IssueId])
+        -> Result<HashMap<IssueId, IssueState>, ApiError>;
+
+    /// Creates the issue and returns its id
+    async fn create_issue(&self, issue: &Issue) -> Result<IssueId, ApiError>;
+
+    /// Creates the labels that don't exist in the repo yet
+    async fn create_labels(&mut self, labels: &[Label]) -> Result<(), ApiError>;
+}
+
 pub fn create_comment_string(todo: &Todo) -> String {
     let mut comment_str = String::new();
     let mut last_str = "";
 
     for comment in &todo.comments {
-        if comment == "" {
-            comment_str.push_str("\n")
+        if comment.is_empty() {
+            comment_str.push('\n')
         } else {
-            if last_str != "" {
-                comment_str.push_str(" ")
+            if !last_str.is_empty() {
+                comment_str.push(' ')
             }
 
             comment_str.push_str(comment)
@@ -61,14 +209,63 @@ pub fn create_comment_string(todo: &Todo) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn status_errors() {
+        assert!(matches!(
+            status_error(StatusCode::UNAUTHORIZED, r#"{"message": "Bad credentials"}"#),
+            ApiError::Unauthorized(m) if m == "Bad credentials"
+        ));
+        assert!(matches!(
+            status_error(StatusCode::NOT_FOUND, ""),
+            ApiError::NotFound(m) if m == "Not Found"
+        ));
+        assert!(matches!(
+            status_error(StatusCode::BAD_GATEWAY, "upstream down"),
+            ApiError::Status(502, m) if m == "upstream down"
+        ))
+    }
+
+    #[test]
+    fn validation_details() {
+        let body = r#"{
+            "message": "Validation Failed",
+            "errors": [{"resource": "Issue", "field": "title", "code": "missing_field"}]
+        }"#;
+
+        assert!(matches!(
+            status_error(StatusCode::UNPROCESSABLE_ENTITY, body),
+            ApiError::Invalid(m) if m == "Validation Failed (title missing_field)"
+        ))
+    }
+
+    #[test]
+    fn jira_errors() {
+        let body = r#"{
+            "errorMessages": [],
+            "errors": {"issuetype": "Specify a valid issue type"}
+        }"#;
+
+        assert!(matches!(
+            status_error(StatusCode::BAD_REQUEST, body),
+            ApiError::Invalid(m) if m == "issuetype: Specify a valid issue type"
+        ));
+        assert!(matches!(
+            status_error(StatusCode::NOT_FOUND, r#"{"errorMessages": ["No project could be found"]}"#),
+            ApiError::NotFound(m) if m == "No project could be found"
+        ))
+    }
+
     #[test]
     fn create_comments_normal() {
         let todo = Todo {
             line: 123,
             prefix: "//".to_owned(),
             keyword: "TODO".to_owned(),
+            kind: "TODO".to_owned(),
+            tag: None,
             title: "Something".to_owned(),
             issue_id: None,
+            issue_repo: None,
             comments: vec!["More".to_owned(), "And More".to_owned()],
         };
 
@@ -81,8 +278,11 @@ mod tests {
             line: 123,
             prefix: "//".to_owned(),
             keyword: "TODO".to_owned(),
+            kind: "TODO".to_owned(),
+            tag: None,
             title: "Something".to_owned(),
             issue_id: None,
+            issue_repo: None,
             comments: vec!["More".to_owned(), "".to_owned(), "And More".to_owned()],
         };
 
@@ -0,0 +1,276 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    RequestBuilder, Response, StatusCode,
+};
+use tokio::{
+    sync::{AcquireError, Semaphore, SemaphorePermit},
+    time::{sleep, sleep_until},
+};
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const DEFAULT_RETRIES: u32 = 3;
+
+const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Longer waits fail the request instead
+const MAX_DELAY: Duration = Duration::from_secs(120);
+
+/// Sends the requests of all backends, so the limits apply to the whole run
+pub struct Scheduler {
+    permits: Semaphore,
+    retries: u32,
+    /// Set when the server asks to wait, no request is sent before
+    paused_until: Mutex<Option<Instant>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Retry {
+    /// The server asked to wait this long
+    Wait(Duration),
+    /// A transient failure, retried with exponential backoff
+    Backoff,
+}
+
+impl Scheduler {
+    pub fn new(concurrency: usize, retries: u32) -> Self {
+        Scheduler {
+            permits: Semaphore::new(concurrency.max(1)),
+            retries,
+            paused_until: Mutex::new(None),
+        }
+    }
+
+    /// Waits until a request may be sent and retries transient failures.
+    /// Requests that aren't idempotent, like creating an issue,
+    /// are only retried if the server didn't process them.
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let idempotent = request
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .is_some_and(|r| r.method().is_idempotent());
+
+        self.send_retrying(request, idempotent).await
+    }
+
+    /// Like `send`, for requests that only read although their method isn't idempotent,
+    /// like a GraphQL query
+    pub async fn send_idempotent(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        self.send_retrying(request, true).await
+    }
+
+    async fn send_retrying(
+        &self,
+        request: RequestBuilder,
+        idempotent: bool,
+    ) -> reqwest::Result<Response> {
+        let mut attempt = 0;
+
+        loop {
+            let current = match request.try_clone() {
+                Some(current) => current,
+                // Streamed bodies can only be sent once
+                None => return self.send_once(request).await,
+            };
+
+            let result = self.send_once(current).await;
+            let retry = match &result {
+                Ok(response) => classify(response.status(), response.headers(), idempotent),
+                Err(e) => classify_error(e, idempotent),
+            };
+
+            let delay = match retry {
+                Some(Retry::Wait(delay)) => delay,
+                Some(Retry::Backoff) => backoff(attempt),
+                None => return result,
+            };
+
+            if attempt >= self.retries || delay > MAX_DELAY {
+                return result;
+            }
+
+            // Every request waits, not only this one
+            if let Some(Retry::Wait(_)) = retry {
+                self.pause(delay);
+            }
+
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send_once(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let paused_until = *self.paused_until.lock().unwrap();
+
+        if let Some(until) = paused_until {
+            sleep_until(until.into()).await;
+        }
+
+        // The semaphore is never closed
+        let _permit = self.permit().await;
+
+        request.send().await
+    }
+
+    /// Also limits work that isn't a request, like the commands of a backend
+    pub async fn permit(&self) -> Result<SemaphorePermit<'_>, AcquireError> {
+        self.permits.acquire().await
+    }
+
+    fn pause(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut paused_until = self.paused_until.lock().unwrap();
+
+        match *paused_until {
+            Some(current) if current >= until => {}
+            _ => *paused_until = Some(until),
+        }
+    }
+}
+
+fn classify(status: StatusCode, headers: &HeaderMap, idempotent: bool) -> Option<Retry> {
+    let limited = status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::FORBIDDEN;
+
+    match wait_time(headers, SystemTime::now()) {
+        Some(wait) if limited => Some(Retry::Wait(wait)),
+        _ if status == StatusCode::TOO_MANY_REQUESTS => Some(Retry::Backoff),
+        _ if status.is_server_error() && idempotent => Some(Retry::Backoff),
+        _ => None,
+    }
+}
+
+/// A request that couldn't connect never reached the server
+fn classify_error(error: &reqwest::Error, idempotent: bool) -> Option<Retry> {
+    if error.is_connect() || error.is_timeout() && idempotent {
+        Some(Retry::Backoff)
+    } else {
+        None
+    }
+}
+
+/// Read from `Retry-After` or the reset of an exhausted rate limit
+fn wait_time(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<u64>().ok();
+
+    if let Some(seconds) = header(RETRY_AFTER.as_str()) {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    if header("x-ratelimit-remaining")? == 0 {
+        let reset = UNIX_EPOCH + Duration::from_secs(header("x-ratelimit-reset")?);
+
+        return Some(reset.duration_since(now).unwrap_or_default());
+    }
+
+    None
+}
+
+fn backoff(attempt: u32) -> Duration {
+    BASE_DELAY * 2u32.saturating_pow(attempt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+
+        headers
+    }
+
+    #[test]
+    fn rate_limit_wait() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            wait_time(&headers(&[("retry-after", "30")]), now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(60)),
+            wait_time(
+                &headers(&[
+                    ("x-ratelimit-remaining", "0"),
+                    ("x-ratelimit-reset", "1060")
+                ]),
+                now
+            )
+        );
+        assert_eq!(
+            None,
+            wait_time(
+                &headers(&[
+                    ("x-ratelimit-remaining", "12"),
+                    ("x-ratelimit-reset", "1060")
+                ]),
+                now
+            )
+        )
+    }
+
+    #[test]
+    fn retry_rate_limits() {
+        assert_eq!(
+            Some(Retry::Wait(Duration::from_secs(5))),
+            classify(
+                StatusCode::FORBIDDEN,
+                &headers(&[("retry-after", "5")]),
+                false
+            )
+        );
+        assert_eq!(
+            Some(Retry::Backoff),
+            classify(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), false)
+        );
+        assert_eq!(
+            None,
+            classify(StatusCode::FORBIDDEN, &HeaderMap::new(), true)
+        )
+    }
+
+    #[test]
+    fn retry_server_errors_if_idempotent() {
+        assert_eq!(
+            Some(Retry::Backoff),
+            classify(StatusCode::BAD_GATEWAY, &HeaderMap::new(), true)
+        );
+        assert_eq!(
+            None,
+            classify(StatusCode::BAD_GATEWAY, &HeaderMap::new(), false)
+        );
+        assert_eq!(
+            None,
+            classify(StatusCode::NOT_FOUND, &HeaderMap::new(), true)
+        )
+    }
+
+    #[tokio::test]
+    async fn retry_idempotent_posts() {
+        let query = mockito::mock("POST", "/scheduler/query")
+            .with_status(502)
+            .expect(2)
+            .create();
+        let request = reqwest::Client::new()
+            .post(&format!("{}/scheduler/query", mockito::server_url()))
+            .body("{}");
+
+        let response = Scheduler::new(1, 1).send_idempotent(request).await.unwrap();
+
+        assert_eq!(StatusCode::BAD_GATEWAY, response.status());
+        query.assert()
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        assert_eq!(Duration::from_secs(1), backoff(0));
+        assert_eq!(Duration::from_secs(8), backoff(3))
+    }
+}
@@ -4,20 +4,38 @@ pub fn create_cli() -> ArgMatches<'static> {
     clap_app!(todo =>
         (@setting ArgRequiredElseHelp)
         (@setting DisableVersion)
+        (@arg overrides: -c --config +takes_value +multiple +global number_of_values(1)
+            "Overrides a config value, e.g. --config user=me")
         (@subcommand files =>
             (about: "Prints all files, filtered after the config")
+            (@arg explain: --explain +takes_value "Shows which pattern includes or excludes the path")
         )
         (@subcommand list =>
             (about: "Lists all (un)reported")
             (@arg reported: -r --reported "Reported todos")
             (@arg unreported: -u --unreported "Unreported todos")
         )
+        (@subcommand stats =>
+            (about: "Counts the TODOs by keyword, directory, file type, author and age")
+            (@arg json: --json "Prints JSON instead of tables")
+        )
         (@subcommand report =>
             (about: "Reports all new TODOs")
         )
         (@subcommand purge =>
             (about: "Purges all closed TODOs")
         )
+        (@subcommand config =>
+            (about: "Inspects the config")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand check =>
+                (about: "Validates the config files")
+            )
+            (@subcommand show =>
+                (about: "Prints the effective config and where each value comes from")
+                (@arg dir: "Directory to show the config of, relative to the root")
+            )
+        )
     )
     .get_matches()
 }
@@ -0,0 +1,285 @@
+use std::{
+    iter::once,
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::{Mapping, Value};
+
+use super::{
+    layers::{merge_layers, Layer, Layers},
+    raw::{Backend, RawConfig, RawToken},
+    remote::{create_policy, create_routes},
+    ConfigError, Scope,
+};
+use crate::Result;
+
+const REDACTED: &str = "<redacted>";
+
+/// The effective config of a directory as yaml,
+/// each value is annotated with the layer it came from
+pub fn show_config(root: &Path, overrides: &[String], dir: &Path) -> Result<String> {
+    let layers = Layers::load(root, overrides)?;
+    let stack = layers.stack(&root.join(dir));
+    let merged = redact(merge_layers(&stack));
+
+    Ok(annotate(&merged, &stack))
+}
+
+/// Validates the config of every directory without connecting to the backends,
+/// a valid config has no problems
+pub fn check_config(root: &Path, overrides: &[String]) -> Result<Vec<String>> {
+    let layers = Layers::load(root, overrides)?;
+    let mut problems = vec![];
+
+    for dir in layers.dirs(root) {
+        let raw = merge_layers(&layers.stack(&dir));
+        let name = match dir.strip_prefix(root) {
+            Ok(relative) if relative.as_os_str().is_empty() => String::new(),
+            Ok(relative) => format!("{}: ", relative.display()),
+            Err(_) => format!("{}: ", dir.display()),
+        };
+
+        for problem in check_raw(root, dir, raw) {
+            problems.push(format!("{}{}", name, problem))
+        }
+    }
+
+    Ok(problems)
+}
+
+fn check_raw(root: &Path, dir: PathBuf, raw: RawConfig) -> Vec<String> {
+    let mut problems = vec![];
+
+    let reported: Vec<_> = raw
+        .keyword_options()
+        .into_iter()
+        .filter(|o| o.report.unwrap_or(true))
+        .collect();
+
+    match create_routes(&raw) {
+        Ok(routes) => {
+            for options in reported.iter() {
+                let unrouted = once(None);
+                let routed = routes.iter().map(|(route, _)| Some(route));
+
+                for route in unrouted.chain(routed) {
+                    if let Err(e) = create_policy(options, &raw, route) {
+                        problems.push(format!("Keyword {}: {}", options.name, e))
+                    }
+                }
+            }
+        }
+        Err(e) => problems.push(e.to_string()),
+    }
+
+    let has_section = |name: &str| raw.backends.iter().any(|b| b.contains_key(name));
+    let missing = match raw.backend {
+        Some(Backend::Command) if !has_section("Command") => Some("backends.Command"),
+        // Commands don't need a token and registered backends check their section themselves
+        Some(Backend::Command) | Some(Backend::Custom(_)) => None,
+        _ if raw.token.is_none() => Some("token"),
+        _ => None,
+    };
+
+    if let (false, Some(key)) = (reported.is_empty(), missing) {
+        problems.push(ConfigError::MissingValue(key.to_owned()).to_string())
+    }
+
+    if let Err(e) = Scope::new(root, dir, raw) {
+        problems.insert(0, e.to_string())
+    }
+
+    problems
+}
+
+/// Token sources are kept, because they only describe where the token is
+fn redact(mut raw: RawConfig) -> RawConfig {
+    if let Some(RawToken::Plain(_)) = raw.token {
+        raw.token = Some(RawToken::Plain(REDACTED.to_owned()))
+    }
+
+    if let Some(ref mut backends) = raw.backends {
+        for section in backends.values_mut() {
+            redact_section(section)
+        }
+    }
+
+    raw
+}
+
+/// Sections can be read by any backend, so every secret looking value is masked
+fn redact_section(value: &mut Value) {
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.as_str().unwrap_or_default().to_lowercase();
+                let secret = ["token", "password", "secret"]
+                    .iter()
+                    .any(|s| key.contains(s));
+
+                match value {
+                    Value::String(_) if secret => *value = Value::String(REDACTED.to_owned()),
+                    value => redact_section(value),
+                }
+            }
+        }
+        Value::Sequence(values) => values.iter_mut().for_each(redact_section),
+        _ => {}
+    }
+}
+
+fn annotate(merged: &RawConfig, layers: &[&Layer]) -> String {
+    let mut output = String::new();
+
+    if let Ok(Value::Mapping(map)) = serde_yaml::to_value(merged).map(strip_nulls) {
+        for (key, value) in map.into_iter() {
+            let name = key.as_str().unwrap_or_default();
+            let mut entry = Mapping::new();
+            entry.insert(key.clone(), value);
+
+            let yaml = serde_yaml::to_string(&entry).unwrap_or_default();
+
+            output.push_str(&format!("# from {}\n", source(name, layers)));
+            output.push_str(yaml.trim_start_matches("---\n"));
+
+            if !output.ends_with('\n') {
+                output.push('\n')
+            }
+        }
+    }
+
+    output
+}
+
+/// Removes unset values, including maps that only contained unset values
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(k, v)| (k, strip_nulls(v)))
+                .filter(|(_, v)| match v {
+                    Value::Null => false,
+                    Value::Mapping(m) => !m.is_empty(),
+                    _ => true,
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// The layers that set a key, values not set by any layer are defaults
+fn source(key: &str, layers: &[&Layer]) -> String {
+    let names: Vec<_> = layers
+        .iter()
+        .filter(|l| is_set(&l.raw, key))
+        .map(|l| l.name.clone())
+        .collect();
+
+    if names.is_empty() {
+        "default".to_owned()
+    } else if key == "paths" {
+        names.join(" + ")
+    } else {
+        names.last().unwrap().clone()
+    }
+}
+
+fn is_set(raw: &RawConfig, key: &str) -> bool {
+    match serde_yaml::to_value(raw) {
+        Ok(Value::Mapping(map)) => map
+            .get(&Value::String(key.to_owned()))
+            .is_some_and(|v| !v.is_null()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::raw::RawPaths;
+
+    fn layer(name: &str, raw: RawConfig) -> Layer {
+        Layer {
+            name: format!("{} ({})", name, name),
+            path: Some(PathBuf::from(name)),
+            raw,
+        }
+    }
+
+    #[test]
+    fn annotate_sources() {
+        let layers = [
+            layer(
+                "global",
+                RawConfig {
+                    backend: Some(Backend::Github),
+                    user: Some("global".to_owned()),
+                    ..Default::default()
+                },
+            ),
+            layer(
+                "local",
+                RawConfig {
+                    user: Some("local".to_owned()),
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let layers: Vec<_> = layers.iter().collect();
+        let output = annotate(&merge_layers(&layers), &layers);
+
+        assert!(output.contains("# from global (global)\nbackend: Github\n"));
+        assert!(output.contains("# from local (local)\nuser: local\n"));
+        assert!(output.contains("# from default\nkeywords:\n  - TODO\n"))
+    }
+
+    #[test]
+    fn redact_plain_token() {
+        let raw = RawConfig {
+            token: Some(RawToken::Plain("secret".to_owned())),
+            ..Default::default()
+        };
+
+        let output = annotate(&redact(raw), &[]);
+
+        assert!(!output.contains("secret"));
+        assert!(output.contains(REDACTED))
+    }
+
+    #[test]
+    fn redact_section_tokens() {
+        let raw: RawConfig = serde_yaml::from_str(
+            "backends:\n  Redmine:\n    api_token: secret\n    auth:\n      password: hunter2\n    project: billing\n    token:\n      env: REDMINE_TOKEN",
+        )
+        .unwrap();
+
+        let output = annotate(&redact(raw), &[]);
+
+        assert!(!output.contains("secret"));
+        assert!(!output.contains("hunter2"));
+        assert!(output.contains("project: billing"));
+        assert!(output.contains("env: REDMINE_TOKEN"))
+    }
+
+    #[test]
+    fn patterns_from_both() {
+        let patterns = |p: &str| RawConfig {
+            paths: Some(RawPaths {
+                include: None,
+                exclude: Some(vec![p.to_owned()]),
+            }),
+            ..Default::default()
+        };
+        let layers = [
+            layer("global", patterns("a")),
+            layer("local", patterns("b")),
+        ];
+
+        assert_eq!(
+            "global (global) + local (local)",
+            source("paths", &layers.iter().collect::<Vec<_>>())
+        )
+    }
+}
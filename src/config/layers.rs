@@ -0,0 +1,344 @@
+use std::path::{Path, PathBuf};
+
+use super::{paths::anchor, raw::RawConfig, token::expand_home, ConfigError};
+use crate::project::find_configs;
+
+/// Keys that can be overridden with a `TODO_<KEY>` environment variable.
+/// `include` isn't one of them, only config files can include other files.
+const ENV_KEYS: &[&str] = &[
+    "backend",
+    "paths",
+    "max_file_size",
+    "max_line_length",
+    "encoding",
+    "keywords",
+    "grammar",
+    "create_labels",
+    "routes",
+    "user",
+    "repo",
+    "token",
+    "url",
+    "backends",
+    "concurrency",
+    "retries",
+];
+
+/// A part of the config and where it comes from
+pub struct Layer {
+    pub name: String,
+    /// Only set for layers read from a file
+    pub path: Option<PathBuf>,
+    pub raw: RawConfig,
+}
+
+/// All layers of a project, from lowest to highest precedence:
+/// global, the `.todo.yml` files from the root down to a directory,
+/// environment variables and the command line.
+/// Included files come right before the file including them.
+pub struct Layers {
+    global: Vec<Layer>,
+    dirs: Vec<(PathBuf, Vec<Layer>)>,
+    overrides: Vec<Layer>,
+}
+
+impl Layers {
+    pub fn load(root: &Path, overrides: &[String]) -> Result<Layers, ConfigError> {
+        let config_dir = dirs::config_dir().ok_or(ConfigError::NoConfigDir)?;
+        let global = load_file("global", &config_dir.join("todo.yml"), &mut vec![])?;
+
+        let mut config_paths = find_configs(root);
+        config_paths.sort_by_key(|p| p.components().count());
+
+        let dirs = config_paths
+            .iter()
+            .map(|path| load_dir(root, path))
+            .collect::<Result<_, _>>()?;
+
+        let mut layers = env_layers()?;
+        layers.append(&mut override_layers(overrides)?);
+
+        Ok(Layers {
+            global,
+            dirs,
+            overrides: layers,
+        })
+    }
+
+    /// The directories that have their own config, the root always comes first
+    pub fn dirs(&self, root: &Path) -> Vec<PathBuf> {
+        let mut dirs = vec![root.to_owned()];
+
+        dirs.extend(
+            self.dirs
+                .iter()
+                .map(|(dir, _)| dir.clone())
+                .filter(|dir| dir != root),
+        );
+
+        dirs
+    }
+
+    /// The layers that apply to a directory
+    pub fn stack(&self, dir: &Path) -> Vec<&Layer> {
+        self.global
+            .iter()
+            .chain(
+                self.dirs
+                    .iter()
+                    .filter(|(d, _)| dir.starts_with(d))
+                    .flat_map(|(_, layers)| layers.iter()),
+            )
+            .chain(self.overrides.iter())
+            .collect()
+    }
+
+    /// All layers read from files
+    pub fn files(&self) -> impl Iterator<Item = &Layer> {
+        self.global
+            .iter()
+            .chain(self.dirs.iter().flat_map(|(_, layers)| layers.iter()))
+    }
+}
+
+pub fn merge_layers(layers: &[&Layer]) -> RawConfig {
+    // Merging two empty configs fills in the defaults
+    let defaults = RawConfig::merge(Default::default(), Default::default());
+
+    layers.iter().fold(defaults, |merged, layer| {
+        RawConfig::merge(merged, layer.raw.clone())
+    })
+}
+
+/// Loads the config of a directory, its patterns are relative to the directory
+fn load_dir(root: &Path, path: &Path) -> Result<(PathBuf, Vec<Layer>), ConfigError> {
+    let dir = path.parent().unwrap_or(root).to_owned();
+    let relative = dir.strip_prefix(root).unwrap_or_else(|_| Path::new(""));
+    let name = if dir == root { "local" } else { "directory" };
+    let mut layers = load_file(name, path, &mut vec![])?;
+
+    for layer in &mut layers {
+        if let Some(paths) = &mut layer.raw.paths {
+            for pattern in paths.include.iter_mut().chain(&mut paths.exclude).flatten() {
+                *pattern = anchor(relative, pattern);
+            }
+        }
+    }
+
+    Ok((dir, layers))
+}
+
+/// Loads a file with its includes, `stack` holds the files currently loading
+fn load_file(name: &str, path: &Path, stack: &mut Vec<PathBuf>) -> Result<Vec<Layer>, ConfigError> {
+    if stack.iter().any(|p| p == path) {
+        return Err(ConfigError::IncludeCycle(path.to_owned()));
+    }
+
+    let raw = RawConfig::from_path(path)?;
+    let mut layers = vec![];
+
+    stack.push(path.to_owned());
+
+    for include in raw.include.iter().flatten() {
+        let include = path
+            .parent()
+            .map(|dir| dir.join(expand_home(include)))
+            .unwrap_or_else(|| expand_home(include));
+
+        if !include.is_file() {
+            return Err(ConfigError::MissingInclude(include));
+        }
+
+        layers.append(&mut load_file("include", &include, stack)?);
+    }
+
+    stack.pop();
+
+    layers.push(Layer {
+        name: format!("{} ({})", name, path.display()),
+        path: Some(path.to_owned()),
+        raw,
+    });
+
+    Ok(layers)
+}
+
+fn env_layers() -> Result<Vec<Layer>, ConfigError> {
+    let mut layers = vec![];
+
+    for key in ENV_KEYS {
+        let var = format!("TODO_{}", key.to_uppercase());
+
+        if let Ok(value) = std::env::var(&var) {
+            let raw = RawConfig::from_override(key, &value)
+                .map_err(|e| ConfigError::Override(var.clone(), e.to_string()))?;
+
+            layers.push(Layer {
+                name: format!("env {}", var),
+                path: None,
+                raw,
+            })
+        }
+    }
+
+    Ok(layers)
+}
+
+/// Parses the `key=value` pairs from the command line
+fn override_layers(overrides: &[String]) -> Result<Vec<Layer>, ConfigError> {
+    overrides
+        .iter()
+        .map(|o| {
+            let (key, value) = o
+                .split_once('=')
+                .ok_or_else(|| ConfigError::Override(o.clone(), "expected key=value".to_owned()))?;
+
+            let raw = RawConfig::from_override(key.trim(), value)
+                .map_err(|e| ConfigError::Override(o.clone(), e.to_string()))?;
+
+            Ok(Layer {
+                name: format!("--config {}", key.trim()),
+                path: None,
+                raw,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::paths::PathRules;
+
+    fn layer(name: &str, raw: RawConfig) -> Layer {
+        Layer {
+            name: name.to_owned(),
+            path: None,
+            raw,
+        }
+    }
+
+    fn user(user: &str) -> RawConfig {
+        RawConfig {
+            user: Some(user.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stack_precedence() {
+        let layers = Layers {
+            global: vec![layer("global", user("global"))],
+            dirs: vec![
+                (PathBuf::from("/repo"), vec![layer("local", user("local"))]),
+                (
+                    PathBuf::from("/repo/web"),
+                    vec![layer("directory", user("web"))],
+                ),
+            ],
+            overrides: vec![],
+        };
+
+        let user_of = |dir: &str| merge_layers(&layers.stack(Path::new(dir))).user;
+
+        assert_eq!(Some("local".to_owned()), user_of("/repo"));
+        assert_eq!(Some("web".to_owned()), user_of("/repo/web/src"));
+        assert_eq!(Some("local".to_owned()), user_of("/repo/webapp"));
+        assert_eq!(
+            vec![PathBuf::from("/repo"), PathBuf::from("/repo/web")],
+            layers.dirs(Path::new("/repo"))
+        )
+    }
+
+    #[test]
+    fn overrides_win() {
+        let layers = Layers {
+            global: vec![],
+            dirs: vec![(
+                PathBuf::from("/repo/web"),
+                vec![layer("directory", user("web"))],
+            )],
+            overrides: override_layers(&["user=cli".to_owned()]).unwrap(),
+        };
+
+        assert_eq!(
+            Some("cli".to_owned()),
+            merge_layers(&layers.stack(Path::new("/repo/web"))).user
+        )
+    }
+
+    #[test]
+    fn env_overrides() {
+        std::env::set_var("TODO_ROUTES", "[{ name: web, paths: [web/], repo: web }]");
+        let layers = env_layers().unwrap();
+        std::env::remove_var("TODO_ROUTES");
+
+        let routes = merge_layers(&layers.iter().collect::<Vec<_>>()).routes;
+
+        assert_eq!(Some(1), routes.map(|r| r.len()))
+    }
+
+    #[test]
+    fn invalid_override() {
+        assert!(matches!(
+            override_layers(&["user".to_owned()]),
+            Err(ConfigError::Override(_, _))
+        ));
+        assert!(matches!(
+            override_layers(&["usr=me".to_owned()]),
+            Err(ConfigError::Override(_, _))
+        ))
+    }
+
+    #[test]
+    fn nested_patterns() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir(root.join("web")).unwrap();
+        fs::write(
+            root.join("web/.todo.yml"),
+            "paths:\n    exclude: [/generated, \"*.min.js\"]\n",
+        )
+        .unwrap();
+
+        let (dir, layers) = load_dir(root, &root.join("web/.todo.yml")).unwrap();
+        let paths = merge_layers(&layers.iter().collect::<Vec<_>>())
+            .paths
+            .unwrap();
+        let rules = PathRules::new(root, &[], &paths.exclude.unwrap()).unwrap();
+        let included = |path: &str| rules.decide(Path::new(path), false).is_included();
+
+        assert_eq!(root.join("web"), dir);
+        assert!(!included("web/generated/api.rs"));
+        assert!(!included("web/js/app.min.js"));
+        assert!(included("generated/api.rs"));
+        assert!(included("web/src/generated/api.rs"));
+        assert!(included("app.min.js"))
+    }
+
+    #[test]
+    fn includes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("shared.yml"), "user: shared\nrepo: shared\n").unwrap();
+        fs::write(
+            dir.join(".todo.yml"),
+            "include: [shared.yml]\nrepo: local\n",
+        )
+        .unwrap();
+        fs::write(dir.join("cycle.yml"), "include: [cycle.yml]\n").unwrap();
+
+        let layers = load_file("local", &dir.join(".todo.yml"), &mut vec![]).unwrap();
+        let merged = merge_layers(&layers.iter().collect::<Vec<_>>());
+
+        assert_eq!(2, layers.len());
+        assert_eq!(Some("shared".to_owned()), merged.user);
+        assert_eq!(Some("local".to_owned()), merged.repo);
+        assert!(matches!(
+            load_file("local", &dir.join("cycle.yml"), &mut vec![]),
+            Err(ConfigError::IncludeCycle(_))
+        ))
+    }
+}
@@ -1,110 +1,346 @@
-use regex::RegexSet;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use encoding_rs::Encoding;
 
 use thiserror::Error;
 
-use self::raw::{Backend, IgnoreMode, RawConfig};
+use self::layers::{merge_layers, Layer, Layers};
+use self::paths::PathRules;
+use self::raw::{RawConfig, RawKeywordOptions, RawToken};
+use self::registry::Registry;
 use crate::{
-    api::{gitea::Gitea, github::Github, Api},
+    grammar::{Grammar, GrammarError, Keyword, DEFAULT_REPORTED, DEFAULT_UNREPORTED},
+    project::is_tracked,
+    source::ReadOptions,
+    todo_parser::DEFAULT_MAX_LINE_LENGTH,
     Result,
 };
 
+pub use self::{
+    paths::Decision,
+    policy::{Policy, Target},
+    raw::Backend,
+    remote::Remote,
+};
+
+pub mod explain;
+mod layers;
+mod paths;
+mod policy;
 mod raw;
+pub mod registry;
+mod remote;
+mod token;
 
 #[derive(Debug, Error)]
 pub enum ConfigError {
-    #[error("Can't compile Paterns!")]
-    Pattern,
-
     #[error("Config misses {0}!")]
     MissingValue(String),
+
+    #[error(transparent)]
+    Grammar(#[from] GrammarError),
+
+    #[error("Can't get token: {0}!")]
+    Token(String),
+
+    #[error("Token file {0} can be read by other users, restrict it with chmod 600!")]
+    InsecureTokenFile(PathBuf),
+
+    #[error("Can't read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+
+    #[error("Invalid config {0}: {1}")]
+    Parse(PathBuf, String),
+
+    #[error("Config has {0} problem(s)!")]
+    Invalid(usize),
+
+    #[error("Included config {0} doesn't exist!")]
+    MissingInclude(PathBuf),
+
+    #[error("Config {0} includes itself!")]
+    IncludeCycle(PathBuf),
+
+    #[error("Unknown encoding {0}!")]
+    UnknownEncoding(String),
+
+    #[error("Invalid glob: {0}")]
+    Glob(String),
+
+    #[error("Invalid override {0}: {1}")]
+    Override(String, String),
+
+    #[error("Backend {0} isn't registered!")]
+    UnknownBackend(String),
+
+    #[error("Backend {0} isn't built-in, add a backends.{0} section if it's registered!")]
+    UndeclaredBackend(String),
+
+    #[error("Invalid section {0} of backends: {1}")]
+    Section(String, String),
+
+    #[error("Can't find the config folder of the global todo.yml, is HOME set?")]
+    NoConfigDir,
+
+    #[error("Host {0} isn't configured, add it as url of a keyword or route to check its issues!")]
+    UndeclaredHost(String),
 }
 
 pub struct Config {
-    pub keywords: Vec<String>,
     pub root: PathBuf,
-    pub filter_fn: Box<dyn Fn(&PathBuf) -> bool>,
-    pub api: Box<dyn Api>,
+    /// Sorted by depth, so the root scope comes first
+    scopes: Vec<Scope>,
+    tracked_tokens: Vec<PathBuf>,
+}
+
+/// The config of a directory, which applies to everything below it
+/// that isn't covered by a deeper directory config
+pub struct Scope {
+    pub dir: PathBuf,
+    pub grammar: Grammar,
+    pub read_options: ReadOptions,
+    /// Longer lines are skipped when searching todos
+    pub max_line_length: usize,
+    paths: PathRules,
+    raw: RawConfig,
 }
 
 impl Config {
-    pub async fn default(root: PathBuf) -> Result<Config> {
-        let raw = RawConfig::merge(
-            RawConfig::from_path(&dirs::config_dir().unwrap().join("todo.yml")),
-            RawConfig::from_path(&root.join(".todo.yml")),
-        );
-
-        if let Ok(patterns) = RegexSet::new(raw.patterns.unwrap()) {
-            let api = create_api(raw.backend, raw.user, raw.repo, raw.token, raw.url).await?;
-
-            Ok(Config {
-                keywords: raw.keywords.unwrap(),
-                root,
-                filter_fn: create_filter_fn(raw.ignore_mode.unwrap(), patterns),
-                api,
+    /// Loads everything needed to work with local files.
+    /// Missing backend settings are only an error when the remote is used.
+    pub fn new(root: PathBuf, overrides: &[String]) -> Result<Config> {
+        let layers = Layers::load(&root, overrides)?;
+        let tracked_tokens = layers
+            .files()
+            .filter(|layer| has_tracked_token(&root, layer))
+            .filter_map(|layer| layer.path.clone())
+            .collect();
+
+        let scopes = layers
+            .dirs(&root)
+            .into_iter()
+            .map(|dir| {
+                let raw = merge_layers(&layers.stack(&dir));
+                Scope::new(&root, dir, raw)
             })
-        } else {
-            Err(ConfigError::Pattern.into())
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Config {
+            root,
+            scopes,
+            tracked_tokens,
+        })
+    }
+
+    /// Config files of the project that contain a token and are tracked by git
+    pub fn tracked_tokens(&self) -> &[PathBuf] {
+        &self.tracked_tokens
+    }
+
+    /// The scope of the directory that contains the path
+    pub fn scope(&self, path: &Path) -> &Scope {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|s| path.starts_with(&s.dir) && path != s.dir)
+            .unwrap_or(&self.scopes[0])
+    }
+
+    /// Checks the path against the patterns of its directory
+    pub fn filter(&self, path: &Path) -> bool {
+        self.decide(path).is_included()
+    }
+
+    /// Why the path is searched or not
+    pub fn decide(&self, path: &Path) -> Decision {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => self.scope(path).paths.decide(relative, path.is_dir()),
+            Err(_) => Decision::OutsideRoot,
         }
     }
+
+    /// The backends for reporting and purging, each repo is connected when it is first used
+    pub async fn remote(&self) -> Result<Remote> {
+        self.remote_with(&Registry::default()).await
+    }
+
+    /// Like `remote`, but the backends are looked up in `registry`
+    pub async fn remote_with(&self, registry: &Registry) -> Result<Remote> {
+        Remote::connect(&self.scopes, registry).await
+    }
 }
 
-async fn create_api(
-    backend: Option<Backend>,
-    user: Option<String>,
-    repo: Option<String>,
-    token: Option<String>,
-    url: Option<String>,
-) -> Result<Box<dyn Api>> {
-    let backend = backend.ok_or(ConfigError::MissingValue("backend".to_owned()))?;
-
-    let user = user.ok_or(ConfigError::MissingValue("user".to_owned()))?;
-    let repo = repo.ok_or(ConfigError::MissingValue("repo".to_owned()))?;
-    let token = token.ok_or(ConfigError::MissingValue("token".to_owned()))?;
-
-    match backend {
-        Backend::Gitea => {
-            let mut url = url.ok_or(ConfigError::MissingValue("url".to_owned()))?;
-            url.push_str("/api/v1");
-
-            Ok(Box::new(Gitea::new(&url, token, &user, &repo).await?))
-        }
-        Backend::Github => Ok(Box::new(Github::new(user, repo, token.clone()).await?)),
+impl Scope {
+    fn new(root: &Path, dir: PathBuf, raw: RawConfig) -> Result<Scope> {
+        raw.check_backends()?;
+
+        let keywords: Vec<_> = raw.keyword_options().iter().map(create_keyword).collect();
+        let templates = raw.grammar.clone().unwrap_or_default();
+        let grammar = Grammar::new(
+            &keywords,
+            templates
+                .unreported
+                .as_deref()
+                .unwrap_or(DEFAULT_UNREPORTED),
+            templates.reported.as_deref().unwrap_or(DEFAULT_REPORTED),
+        )
+        .and_then(|grammar| {
+            if uses_jira(&raw) {
+                grammar.with_issue_keys()
+            } else {
+                Ok(grammar)
+            }
+        })
+        .map_err(ConfigError::from)?;
+
+        let patterns = raw.paths.clone().unwrap_or_default();
+        let paths = PathRules::new(
+            root,
+            &patterns.include.unwrap_or_default(),
+            &patterns.exclude.unwrap_or_default(),
+        )?;
+
+        let fallback = match raw.encoding {
+            Some(ref label) => Some(
+                Encoding::for_label(label.as_bytes())
+                    .ok_or_else(|| ConfigError::UnknownEncoding(label.clone()))?,
+            ),
+            None => None,
+        };
+        let read_options = ReadOptions {
+            max_size: raw.max_file_size,
+            fallback,
+        };
+
+        Ok(Scope {
+            dir,
+            grammar,
+            read_options,
+            max_line_length: raw.max_line_length.unwrap_or(DEFAULT_MAX_LINE_LENGTH),
+            paths,
+            raw,
+        })
     }
 }
 
-fn create_filter_fn(mode: IgnoreMode, regex_set: RegexSet) -> Box<dyn Fn(&PathBuf) -> bool> {
-    match mode {
-        IgnoreMode::Blacklist => {
-            Box::new(move |path: &PathBuf| !regex_set.is_match(&path.to_string_lossy()))
-        }
-        IgnoreMode::Whitelist => {
-            Box::new(move |path: &PathBuf| regex_set.is_match(&path.to_string_lossy()))
-        }
+/// Issue keys like `PROJ-12` are only parsed if a keyword or route reports to Jira
+fn uses_jira(raw: &RawConfig) -> bool {
+    raw.used_backends().contains(&Backend::Jira)
+}
+
+fn has_tracked_token(root: &Path, layer: &Layer) -> bool {
+    match (&layer.path, &layer.raw.token) {
+        (Some(path), Some(RawToken::Plain(_))) => path.starts_with(root) && is_tracked(path),
+        _ => false,
+    }
+}
+
+fn create_keyword(options: &RawKeywordOptions) -> Keyword {
+    Keyword {
+        name: options.name.clone(),
+        aliases: options.aliases.clone().unwrap_or_default(),
+        case_sensitive: options.case_sensitive.unwrap_or(true),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::raw::{RawGrammar, RawKeyword, RawRoute};
+
+    fn scope(raw: RawConfig) -> Result<Scope> {
+        let raw = RawConfig::merge(RawConfig::default(), raw);
+
+        Scope::new(Path::new("/repo"), PathBuf::from("/repo"), raw)
+    }
+
+    #[test]
+    fn decide_outside_root() {
+        let conf = Config {
+            root: PathBuf::from("/repo"),
+            scopes: vec![scope(RawConfig::default()).unwrap()],
+            tracked_tokens: vec![],
+        };
+
+        assert_eq!(Decision::OutsideRoot, conf.decide(Path::new("/etc/passwd")));
+        assert!(conf.decide(Path::new("/repo/src/main.rs")).is_included())
+    }
 
     #[test]
-    fn blacklist_filter() {
-        let filter = create_filter_fn(
-            IgnoreMode::Blacklist,
-            RegexSet::new(&["hallo(.*)"]).unwrap(),
-        );
+    fn issue_keys_only_with_jira() {
+        let line = "// TODO(PROJ-12): Something";
+        let github = scope(RawConfig {
+            backend: Some(Backend::Github),
+            ..Default::default()
+        })
+        .unwrap();
+        let jira = scope(RawConfig {
+            routes: Some(vec![RawRoute {
+                name: "ops".to_owned(),
+                backend: Some(Backend::Jira),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        })
+        .unwrap();
 
-        assert_eq!(false, filter(&PathBuf::from("hallo123")))
+        assert_eq!(None, github.grammar.parse_line(line));
+        assert!(jira.grammar.parse_line(line).unwrap().issue_id.is_some())
     }
 
     #[test]
-    fn whitelist_filter() {
-        let filter = create_filter_fn(
-            IgnoreMode::Whitelist,
-            RegexSet::new(&["hallo(.*)"]).unwrap(),
-        );
+    fn scope_defaults() {
+        let scope = scope(RawConfig::default()).unwrap();
 
-        assert_eq!(true, filter(&PathBuf::from("hallo123")))
+        assert!(scope.grammar.parse_line("// TODO: Something").is_some())
+    }
+
+    #[test]
+    fn invalid_scopes() {
+        let keywords = |names: &[&str]| {
+            Some(
+                names
+                    .iter()
+                    .map(|n| RawKeyword::Name(n.to_string()))
+                    .collect(),
+            )
+        };
+
+        assert!(scope(RawConfig {
+            keywords: keywords(&[]),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(scope(RawConfig {
+            keywords: keywords(&["TODO", ""]),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(scope(RawConfig {
+            grammar: Some(RawGrammar {
+                unreported: Some("{prefix} {keyword}: {title".to_owned()),
+                reported: None,
+            }),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(matches!(
+            scope(RawConfig {
+                encoding: Some("klingon".to_owned()),
+                ..Default::default()
+            }),
+            Err(crate::TodoError::Config(ConfigError::UnknownEncoding(_)))
+        ))
+    }
+
+    #[test]
+    fn keyword_defaults() {
+        assert_eq!(
+            Keyword::new("TODO"),
+            create_keyword(&RawKeywordOptions {
+                name: "TODO".to_owned(),
+                ..Default::default()
+            })
+        )
     }
 }
@@ -0,0 +1,201 @@
+use std::{fmt::Display, path::Path};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+
+use super::ConfigError;
+
+/// Always excluded, the files of git are never searched for todos
+const BUILTIN_EXCLUDE: &str = ".git/";
+
+/// Decides with gitignore style patterns which files are searched.
+/// All paths are relative to the root of the project.
+pub struct PathRules {
+    include: Gitignore,
+    exclude: Gitignore,
+}
+
+/// Why a path is searched or not
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Included by the pattern, or by default if no pattern matched
+    Included(Option<String>),
+    /// Excluded by the pattern
+    Excluded(String),
+    /// Include patterns exist, but none of them matched
+    NotIncluded,
+    /// The patterns only apply to paths in the project
+    OutsideRoot,
+}
+
+impl Decision {
+    pub fn is_included(&self) -> bool {
+        matches!(self, Decision::Included(_))
+    }
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Included(Some(pattern)) => write!(f, "included by \"{}\"", pattern),
+            Decision::Included(None) => write!(f, "included, no pattern matched"),
+            Decision::Excluded(pattern) => write!(f, "excluded by \"{}\"", pattern),
+            Decision::NotIncluded => write!(f, "excluded, no include pattern matched"),
+            Decision::OutsideRoot => write!(f, "excluded, outside of the project root"),
+        }
+    }
+}
+
+impl PathRules {
+    pub fn new(root: &Path, include: &[String], exclude: &[String]) -> Result<Self, ConfigError> {
+        let exclude: Vec<_> = Some(BUILTIN_EXCLUDE.to_owned())
+            .into_iter()
+            .chain(exclude.iter().cloned())
+            .collect();
+
+        Ok(PathRules {
+            include: build(root, include)?,
+            exclude: build(root, &exclude)?,
+        })
+    }
+
+    /// Directories are only checked against the exclude patterns,
+    /// so include patterns like `src/**/*.rs` don't hide the directories of the files
+    pub fn decide(&self, relative: &Path, is_dir: bool) -> Decision {
+        let mut negated = None;
+
+        match self.exclude.matched_path_or_any_parents(relative, is_dir) {
+            Match::Ignore(glob) => return Decision::Excluded(glob.original().to_owned()),
+            Match::Whitelist(glob) => negated = Some(glob.original().to_owned()),
+            Match::None => {}
+        }
+
+        if is_dir || self.include.is_empty() {
+            return Decision::Included(negated);
+        }
+
+        match self.include.matched_path_or_any_parents(relative, is_dir) {
+            Match::Ignore(glob) => Decision::Included(Some(glob.original().to_owned())),
+            _ => Decision::NotIncluded,
+        }
+    }
+}
+
+/// Makes a pattern of the config in `dir` relative to the root, like git does for a nested .gitignore.
+/// Patterns with a slash are anchored at `dir`, the others match at any depth below it.
+pub fn anchor(dir: &Path, pattern: &str) -> String {
+    let (negation, rest) = match pattern.strip_prefix('!') {
+        Some(rest) => ("!", rest),
+        None => ("", pattern),
+    };
+
+    if dir.as_os_str().is_empty() || rest.is_empty() || pattern.starts_with('#') {
+        return pattern.to_owned();
+    }
+
+    let base: Vec<_> = dir
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    let anchored = rest.trim_end_matches('/').contains('/');
+    let rest = rest.strip_prefix('/').unwrap_or(rest);
+
+    if anchored {
+        format!("{}/{}/{}", negation, base.join("/"), rest)
+    } else {
+        format!("{}/{}/**/{}", negation, base.join("/"), rest)
+    }
+}
+
+fn build(root: &Path, patterns: &[String]) -> Result<Gitignore, ConfigError> {
+    let mut builder = GitignoreBuilder::new(root);
+
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| ConfigError::Glob(e.to_string()))?;
+    }
+
+    builder
+        .build()
+        .map_err(|e| ConfigError::Glob(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(include: &[&str], exclude: &[&str]) -> PathRules {
+        let owned = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        PathRules::new(Path::new("/repo"), &owned(include), &owned(exclude)).unwrap()
+    }
+
+    #[test]
+    fn exclude_relative() {
+        let rules = rules(&[], &["target/", "*.lock"]);
+
+        assert_eq!(
+            Decision::Excluded("target/".to_owned()),
+            rules.decide(Path::new("target/debug/main.rs"), false)
+        );
+        assert_eq!(
+            Decision::Excluded("*.lock".to_owned()),
+            rules.decide(Path::new("Cargo.lock"), false)
+        );
+        assert_eq!(
+            Decision::Included(None),
+            rules.decide(Path::new("src/target.rs"), false)
+        );
+        assert_eq!(
+            Decision::Excluded(".git/".to_owned()),
+            rules.decide(Path::new(".git"), true)
+        )
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let rules = rules(
+            &["src/**/*.rs"],
+            &["src/generated/*", "!src/generated/keep.rs"],
+        );
+
+        assert_eq!(
+            Decision::Included(Some("src/**/*.rs".to_owned())),
+            rules.decide(Path::new("src/main.rs"), false)
+        );
+        assert_eq!(
+            Decision::NotIncluded,
+            rules.decide(Path::new("README.md"), false)
+        );
+        assert_eq!(
+            Decision::Excluded("src/generated/*".to_owned()),
+            rules.decide(Path::new("src/generated/other.rs"), false)
+        );
+        assert!(rules
+            .decide(Path::new("src/generated/keep.rs"), false)
+            .is_included());
+        assert!(rules.decide(Path::new("docs"), true).is_included())
+    }
+
+    #[test]
+    fn anchor_nested() {
+        let dir = Path::new("web/app");
+
+        assert_eq!("/web/app/generated", anchor(dir, "/generated"));
+        assert_eq!("/web/app/src/*.js", anchor(dir, "src/*.js"));
+        assert_eq!("!/web/app/**/*.min.js", anchor(dir, "!*.min.js"));
+        assert_eq!("/web/app/**/dist/", anchor(dir, "dist/"));
+        assert_eq!("dist/", anchor(Path::new(""), "dist/"))
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(matches!(
+            PathRules::new(Path::new("/repo"), &["src/{a".to_owned()], &[]),
+            Err(ConfigError::Glob(_))
+        ))
+    }
+}
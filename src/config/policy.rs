@@ -0,0 +1,235 @@
+use regex::{Captures, Regex};
+
+use super::raw::Backend;
+use crate::{
+    api::{create_comment_string, Issue, Label},
+    todo::Todo,
+};
+
+const DEFAULT_LABEL_COLOR: &str = "ededed";
+/// Forgejo instance used when no url is configured
+pub const CODEBERG_URL: &str = "https://codeberg.org";
+/// Where the issues of Github repos are shown
+pub const GITHUB_URL: &str = "https://github.com";
+
+/// The repository issues are reported to
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Target {
+    pub backend: Backend,
+    pub user: String,
+    pub repo: String,
+    pub url: Option<String>,
+}
+
+impl Target {
+    /// The url of the instance, Github doesn't need one
+    pub fn instance_url(&self) -> Option<&str> {
+        match self.backend {
+            Backend::Forgejo => Some(self.url.as_deref().unwrap_or(CODEBERG_URL)),
+            _ => self.url.as_deref(),
+        }
+    }
+}
+
+/// Describes how the todos of a keyword are reported
+#[derive(Debug, PartialEq, Clone)]
+pub struct Policy {
+    /// Todos that aren't reported are only listed
+    pub report: bool,
+    /// Defaults to a label with the name of the keyword
+    pub labels: Option<Vec<String>>,
+    /// Used when the labels are created
+    pub label_color: Option<String>,
+    pub label_description: Option<String>,
+    pub milestone: Option<String>,
+    pub assignees: Vec<String>,
+    /// Template for the issue title, can use {title}, {keyword} and {tag}
+    pub title: Option<String>,
+    pub issue_type: Option<String>,
+    pub target: Target,
+    /// Name of the route the target comes from
+    pub route: Option<String>,
+}
+
+impl Policy {
+    pub fn new(target: Target) -> Policy {
+        Policy {
+            report: true,
+            labels: None,
+            label_color: None,
+            label_description: None,
+            milestone: None,
+            assignees: vec![],
+            title: None,
+            issue_type: None,
+            target,
+            route: None,
+        }
+    }
+
+    pub fn issue(&self, todo: &Todo) -> Issue {
+        let title = match self.title {
+            Some(ref template) => render_title(template, todo),
+            None => todo.title.clone(),
+        };
+
+        Issue {
+            title,
+            body: create_comment_string(todo),
+            labels: self.label_names(&todo.kind),
+            milestone: self.milestone.clone(),
+            assignees: self.assignees.clone(),
+            issue_type: self.issue_type.clone(),
+        }
+    }
+
+    fn label_names(&self, kind: &str) -> Vec<String> {
+        self.labels.clone().unwrap_or_else(|| vec![kind.to_owned()])
+    }
+
+    /// The labels the issues of the keyword can get
+    pub fn labels(&self, kind: &str) -> Vec<Label> {
+        self.label_names(kind)
+            .into_iter()
+            .map(|name| Label {
+                name,
+                color: self
+                    .label_color
+                    .as_deref()
+                    .unwrap_or(DEFAULT_LABEL_COLOR)
+                    .trim_start_matches('#')
+                    .to_owned(),
+                description: self.label_description.clone().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Fills all placeholders in one pass, so placeholders in the title of the todo are kept
+fn render_title(template: &str, todo: &Todo) -> String {
+    let placeholder = Regex::new(r"\{(title|keyword|tag)\}").unwrap();
+
+    placeholder
+        .replace_all(template, |c: &Captures| match &c[1] {
+            "title" => todo.title.clone(),
+            "keyword" => todo.kind.clone(),
+            _ => todo.tag.clone().unwrap_or_default(),
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> Target {
+        Target {
+            backend: Backend::Github,
+            user: "user".to_owned(),
+            repo: "repo".to_owned(),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn forgejo_defaults_to_codeberg() {
+        let forgejo = Target {
+            backend: Backend::Forgejo,
+            ..target()
+        };
+        let gitea = Target {
+            backend: Backend::Gitea,
+            ..target()
+        };
+
+        assert_eq!(Some(CODEBERG_URL), forgejo.instance_url());
+        assert_eq!(None, gitea.instance_url())
+    }
+
+    fn todo() -> Todo {
+        Todo {
+            line: 1,
+            prefix: "//".to_owned(),
+            keyword: "fixme".to_owned(),
+            kind: "FIXME".to_owned(),
+            tag: Some("john".to_owned()),
+            title: "Something".to_owned(),
+            issue_id: None,
+            issue_repo: None,
+            comments: vec!["More".to_owned()],
+        }
+    }
+
+    #[test]
+    fn default_issue() {
+        assert_eq!(
+            Issue {
+                title: "Something".to_owned(),
+                body: "More".to_owned(),
+                labels: vec!["FIXME".to_owned()],
+                ..Default::default()
+            },
+            Policy::new(target()).issue(&todo())
+        )
+    }
+
+    #[test]
+    fn configured_issue() {
+        let policy = Policy {
+            labels: Some(vec!["bug".to_owned(), "triage".to_owned()]),
+            milestone: Some("v1.0".to_owned()),
+            assignees: vec!["alice".to_owned()],
+            title: Some("[{keyword}] {title} ({tag})".to_owned()),
+            issue_type: Some("Bug".to_owned()),
+            ..Policy::new(target())
+        };
+
+        assert_eq!(
+            Issue {
+                title: "[FIXME] Something (john)".to_owned(),
+                body: "More".to_owned(),
+                labels: vec!["bug".to_owned(), "triage".to_owned()],
+                milestone: Some("v1.0".to_owned()),
+                assignees: vec!["alice".to_owned()],
+                issue_type: Some("Bug".to_owned()),
+            },
+            policy.issue(&todo())
+        )
+    }
+
+    #[test]
+    fn placeholders_in_title() {
+        let policy = Policy {
+            title: Some("{title} ({tag}, {keyword})".to_owned()),
+            ..Policy::new(target())
+        };
+        let todo = Todo {
+            title: "Replace {tag} with {keyword}".to_owned(),
+            ..todo()
+        };
+
+        assert_eq!(
+            "Replace {tag} with {keyword} (john, FIXME)",
+            policy.issue(&todo).title
+        )
+    }
+
+    #[test]
+    fn labels_to_create() {
+        let policy = Policy {
+            label_color: Some("#d73a4a".to_owned()),
+            label_description: Some("Something is broken".to_owned()),
+            ..Policy::new(target())
+        };
+
+        assert_eq!(
+            vec![Label {
+                name: "BUG".to_owned(),
+                color: "d73a4a".to_owned(),
+                description: "Something is broken".to_owned(),
+            }],
+            policy.labels("BUG")
+        );
+        assert_eq!("ededed", Policy::new(target()).labels("BUG")[0].color)
+    }
+}
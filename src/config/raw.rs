@@ -1,99 +1,439 @@
-use std::{fs::read_to_string, path::Path};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::read_to_string,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use super::{token::TokenSource, ConfigError};
+use serde_yaml::{Mapping, Value};
+
+/// A built-in backend or the name of a registered one
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Backend {
+    Gitea,
+    Github,
+    /// A fork of Gitea with the same api, like Codeberg
+    Forgejo,
+    /// The repo is the key of the project and the user the email of the account
+    Jira,
+    /// Any tracker, reported to by shell commands
+    Command,
+    /// Registered by library code
+    Custom(String),
+}
 
-use serde::Deserialize;
+impl Backend {
+    pub fn name(&self) -> &str {
+        match self {
+            Backend::Gitea => "Gitea",
+            Backend::Github => "Github",
+            Backend::Forgejo => "Forgejo",
+            Backend::Jira => "Jira",
+            Backend::Command => "Command",
+            Backend::Custom(name) => name,
+        }
+    }
 
-#[derive(Debug, Deserialize, PartialEq)]
-pub enum IgnoreMode {
-    Blacklist,
-    Whitelist,
+    /// Commands and registered backends are configured in their section,
+    /// so they don't need a user and repo
+    pub fn needs_repo(&self) -> bool {
+        !matches!(self, Backend::Command | Backend::Custom(_))
+    }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
-pub enum Backend {
-    Gitea,
-    Github,
+impl From<&str> for Backend {
+    fn from(name: &str) -> Self {
+        match name {
+            "Gitea" => Backend::Gitea,
+            "Github" => Backend::Github,
+            "Forgejo" => Backend::Forgejo,
+            "Jira" => Backend::Jira,
+            "Command" => Backend::Command,
+            name => Backend::Custom(name.to_owned()),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-pub struct RawConfig {
-    pub ignore_mode: Option<IgnoreMode>,
+impl Serialize for Backend {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Backend {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|name| Backend::from(name.as_str()))
+    }
+}
+
+/// A token is either written in the config or read from a source
+#[derive(Debug, Serialize, PartialEq, Eq, Hash, Clone)]
+#[serde(untagged)]
+pub enum RawToken {
+    Source(TokenSource),
+    Plain(String),
+}
+
+impl<'de> Deserialize<'de> for RawToken {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TokenVisitor;
+
+        impl<'de> Visitor<'de> for TokenVisitor {
+            type Value = RawToken;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a token, git-credential or a map with env, command or file")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<RawToken, E> {
+                if value == "git-credential" {
+                    Ok(RawToken::Source(TokenSource::GitCredential))
+                } else {
+                    Ok(RawToken::Plain(value.to_owned()))
+                }
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<RawToken, A::Error> {
+                TokenSource::deserialize(MapAccessDeserializer::new(map)).map(RawToken::Source)
+            }
+        }
+
+        deserializer.deserialize_any(TokenVisitor)
+    }
+}
+
+/// A keyword is either just its name or a map with options
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum RawKeyword {
+    Name(String),
+    Options(Box<RawKeywordOptions>),
+}
+
+// Implemented by hand, so that typos in the options aren't hidden
+// behind a generic error of an untagged enum
+impl<'de> Deserialize<'de> for RawKeyword {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct KeywordVisitor;
+
+        impl<'de> Visitor<'de> for KeywordVisitor {
+            type Value = RawKeyword;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a keyword or a map with keyword options")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<RawKeyword, E> {
+                Ok(RawKeyword::Name(value.to_owned()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<RawKeyword, A::Error> {
+                RawKeywordOptions::deserialize(MapAccessDeserializer::new(map))
+                    .map(|o| RawKeyword::Options(Box::new(o)))
+            }
+        }
+
+        deserializer.deserialize_any(KeywordVisitor)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawKeywordOptions {
+    pub name: String,
+    pub case_sensitive: Option<bool>,
+    pub aliases: Option<Vec<String>>,
+
+    pub report: Option<bool>,
+    pub labels: Option<Vec<String>>,
+    pub label_color: Option<String>,
+    pub label_description: Option<String>,
+    pub milestone: Option<String>,
+    pub assignees: Option<Vec<String>>,
+    pub title: Option<String>,
+    pub issue_type: Option<String>,
+
     pub backend: Option<Backend>,
-    pub patterns: Option<Vec<String>>,
-    pub keywords: Option<Vec<String>>,
     pub user: Option<String>,
     pub repo: Option<String>,
-    pub token: Option<String>,
     pub url: Option<String>,
 }
 
-impl Default for RawConfig {
-    fn default() -> Self {
-        RawConfig {
-            ignore_mode: None,
-            patterns: None,
-            keywords: None,
-            backend: None,
-            user: None,
-            repo: None,
-            token: None,
-            url: None,
+/// Todos in files matching one of the paths are reported to the route's repo
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawRoute {
+    /// Written before the issue id, like `billing#12`
+    pub name: String,
+    /// Globs relative to the root, a directory also matches everything below it
+    pub paths: Vec<String>,
+
+    pub backend: Option<Backend>,
+    pub user: Option<String>,
+    pub repo: Option<String>,
+    pub url: Option<String>,
+}
+
+impl RawKeyword {
+    pub fn into_options(self) -> RawKeywordOptions {
+        match self {
+            RawKeyword::Name(name) => RawKeywordOptions {
+                name,
+                ..Default::default()
+            },
+            RawKeyword::Options(options) => *options,
         }
     }
 }
 
+/// Gitignore style patterns, relative to the root
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawPaths {
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+}
+
+impl RawPaths {
+    /// The patterns of both are used, so the local ones can negate global ones
+    fn merge(global: Self, local: Self) -> Self {
+        let concat = |g: Option<Vec<String>>, l: Option<Vec<String>>| {
+            merge_fn(g, l, vec![], |mut g, mut l| {
+                g.append(&mut l);
+                g
+            })
+        };
+
+        RawPaths {
+            include: concat(global.include, local.include),
+            exclude: concat(global.exclude, local.exclude),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawGrammar {
+    pub unreported: Option<String>,
+    pub reported: Option<String>,
+}
+
+impl RawGrammar {
+    fn merge(global: Self, local: Self) -> Self {
+        RawGrammar {
+            unreported: merge(global.unreported, local.unreported),
+            reported: merge(global.reported, local.reported),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RawConfig {
+    /// Files loaded before this one, relative to its directory
+    pub include: Option<Vec<PathBuf>>,
+    pub backend: Option<Backend>,
+    pub paths: Option<RawPaths>,
+    /// Larger files are skipped
+    pub max_file_size: Option<u64>,
+    /// Longer lines are skipped when searching todos
+    pub max_line_length: Option<usize>,
+    /// Used for files that aren't UTF-8 and have no BOM, like latin1 or utf-16le
+    pub encoding: Option<String>,
+    pub keywords: Option<Vec<RawKeyword>>,
+    pub grammar: Option<RawGrammar>,
+    pub create_labels: Option<bool>,
+    pub routes: Option<Vec<RawRoute>>,
+    pub user: Option<String>,
+    pub repo: Option<String>,
+    pub token: Option<RawToken>,
+    pub url: Option<String>,
+    /// Config of a backend by its name, read by the backend itself
+    pub backends: Option<BTreeMap<String, Value>>,
+    /// Requests that are sent at the same time, by all backends
+    pub concurrency: Option<usize>,
+    /// How often failed requests are retried
+    pub retries: Option<u32>,
+}
+
+/// Keys of older versions, with how they're written now
+const REMOVED_KEYS: &[(&str, &str)] = &[
+    (
+        "patterns",
+        "was replaced by `paths.exclude`, or `paths.include` with `ignore_mode: Whitelist`",
+    ),
+    (
+        "ignore_mode",
+        "was removed, put blacklisted patterns into `paths.exclude` and whitelisted ones into `paths.include`",
+    ),
+];
+
+/// Removed keys get an error that names their replacement instead of an unknown field
+fn check_removed(value: &Value) -> Result<(), serde_yaml::Error> {
+    let removed = REMOVED_KEYS
+        .iter()
+        .find(|(key, _)| value.get(key).is_some());
+
+    match removed {
+        Some((key, hint)) => Err(de::Error::custom(format!("`{}` {}", key, hint))),
+        None => Ok(()),
+    }
+}
+
 impl RawConfig {
-    pub fn from_path(path: &Path) -> Self {
-        read_to_string(path)
-            .ok()
-            .and_then(|t| serde_yaml::from_str(&t).ok())
-            .unwrap_or(Default::default())
+    /// A missing file is treated as an empty config
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        match read_to_string(path) {
+            Ok(text) => RawConfig::from_str(&text)
+                .map_err(|e| ConfigError::Parse(path.to_owned(), e.to_string())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Default::default()),
+            Err(e) => Err(ConfigError::Read(path.to_owned(), e)),
+        }
     }
 
-    pub fn merge(global: Self, local: Self) -> Self {
-        let mut same_mode = true;
-
-        let ignore_mode = merge_fn(
-            global.ignore_mode,
-            local.ignore_mode,
-            IgnoreMode::Blacklist,
-            |g_mode, l_mode| {
-                if g_mode != l_mode {
-                    same_mode = false;
-                }
+    fn from_str(text: &str) -> Result<Self, serde_yaml::Error> {
+        let is_empty = text
+            .lines()
+            .map(str::trim)
+            .all(|l| l.is_empty() || l.starts_with('#') || l == "---");
 
-                l_mode
-            },
-        );
+        if is_empty {
+            return Ok(Default::default());
+        }
 
-        let patterns = merge_fn(global.patterns, local.patterns, vec![], |mut g, mut l| {
-            if same_mode {
-                l.append(&mut g);
-            }
-            l
-        });
+        // Parsed twice, so other errors keep their line and column
+        if let Ok(value) = serde_yaml::from_str(text) {
+            check_removed(&value)?;
+        }
+
+        serde_yaml::from_str(text)
+    }
+
+    /// Builds a config with a single value, nested keys are separated by dots.
+    /// The value is parsed as yaml, so lists can also be set.
+    pub fn from_override(key: &str, value: &str) -> Result<Self, serde_yaml::Error> {
+        let mut value: Value = serde_yaml::from_str(value)?;
+
+        for part in key.rsplit('.') {
+            let mut map = Mapping::new();
+            map.insert(Value::String(part.to_owned()), value);
+            value = Value::Mapping(map);
+        }
+
+        check_removed(&value)?;
+        serde_yaml::from_value(value)
+    }
+
+    /// The options of all keywords, names are treated as keywords without options
+    pub fn keyword_options(&self) -> Vec<RawKeywordOptions> {
+        self.keywords
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(RawKeyword::into_options)
+            .collect()
+    }
+
+    /// The backends set globally, by keywords and by routes
+    pub fn used_backends(&self) -> Vec<Backend> {
+        let keywords = self.keyword_options().into_iter().map(|k| k.backend);
+        let routes = self.routes.iter().flatten().map(|r| r.backend.clone());
+
+        std::iter::once(self.backend.clone())
+            .chain(keywords)
+            .chain(routes)
+            .flatten()
+            .collect()
+    }
+
+    /// Backends that aren't built-in have to be declared with a section in `backends`,
+    /// so a misspelled name isn't taken for a registered backend
+    pub fn check_backends(&self) -> Result<(), ConfigError> {
+        let declared = |name: &str| self.backends.iter().any(|b| b.contains_key(name));
+
+        match self.used_backends().into_iter().find(|b| match b {
+            Backend::Custom(name) => !declared(name),
+            _ => false,
+        }) {
+            Some(backend) => Err(ConfigError::UndeclaredBackend(backend.name().to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn merge(global: Self, local: Self) -> Self {
+        let paths = merge_fn(
+            global.paths,
+            local.paths,
+            RawPaths::default(),
+            RawPaths::merge,
+        );
 
         let keywords = merge_fn(
             global.keywords,
             local.keywords,
-            vec!["TODO".to_string()],
+            vec![RawKeyword::Name("TODO".to_string())],
             |_, l| l,
         );
 
+        let grammar = merge_fn(
+            global.grammar,
+            local.grammar,
+            RawGrammar::default(),
+            RawGrammar::merge,
+        );
+
+        let create_labels = merge(global.create_labels, local.create_labels);
         let backend = merge(global.backend, local.backend);
         let user = merge(global.user, local.user);
         let repo = merge(global.repo, local.repo);
         let token = merge(global.token, local.token);
         let url = merge(global.url, local.url);
+        // The sections of different backends are kept
+        let backends = match (global.backends, local.backends) {
+            (Some(mut global), Some(local)) => {
+                global.extend(local);
+                Some(global)
+            }
+            (global, local) => local.or(global),
+        };
+        let routes = merge(global.routes, local.routes);
+        let max_file_size = merge(global.max_file_size, local.max_file_size);
+        let max_line_length = merge(global.max_line_length, local.max_line_length);
+        let encoding = merge(global.encoding, local.encoding);
+        let concurrency = merge(global.concurrency, local.concurrency);
+        let retries = merge(global.retries, local.retries);
 
         RawConfig {
-            ignore_mode,
-            patterns,
+            include: None,
+            paths,
+            max_file_size,
+            max_line_length,
+            encoding,
             keywords,
+            grammar,
+            create_labels,
+            routes,
             backend,
             user,
             repo,
             token,
             url,
+            backends,
+            concurrency,
+            retries,
         }
     }
 }
@@ -174,9 +514,9 @@ mod tests {
         fn merge_empty() {
             assert_eq!(
                 RawConfig {
-                    ignore_mode: Some(IgnoreMode::Blacklist),
-                    patterns: Some(vec![]),
-                    keywords: Some(vec!["TODO".to_owned()]),
+                    paths: Some(RawPaths::default()),
+                    keywords: Some(vec![RawKeyword::Name("TODO".to_owned())]),
+                    grammar: Some(RawGrammar::default()),
                     ..Default::default()
                 },
                 RawConfig::merge(Default::default(), Default::default())
@@ -186,24 +526,26 @@ mod tests {
         #[test]
         fn merge_priority() {
             let local = RawConfig {
-                ignore_mode: Some(IgnoreMode::Whitelist),
-                keywords: Some(vec!["TODO".to_string()]),
-                patterns: Some(vec![".git".to_string()]),
+                keywords: Some(vec![RawKeyword::Name("TODO".to_string())]),
+                user: Some("local".to_string()),
                 ..Default::default()
             };
 
             let global: RawConfig = RawConfig {
-                ignore_mode: Some(IgnoreMode::Blacklist),
-                keywords: Some(vec!["FIXME".to_string(), "BUG".to_string()]),
-                patterns: Some(vec!["target".to_string()]),
+                keywords: Some(vec![
+                    RawKeyword::Name("FIXME".to_string()),
+                    RawKeyword::Name("BUG".to_string()),
+                ]),
+                user: Some("global".to_string()),
                 ..Default::default()
             };
 
             assert_eq!(
                 RawConfig {
-                    ignore_mode: Some(IgnoreMode::Whitelist),
-                    patterns: Some(vec![".git".to_string()]),
-                    keywords: Some(vec!["TODO".to_string()]),
+                    paths: Some(RawPaths::default()),
+                    keywords: Some(vec![RawKeyword::Name("TODO".to_string())]),
+                    grammar: Some(RawGrammar::default()),
+                    user: Some("local".to_string()),
                     ..Default::default()
                 },
                 RawConfig::merge(global, local)
@@ -211,32 +553,233 @@ mod tests {
         }
 
         #[test]
-        fn merge_add_patterns() {
+        fn merge_add_paths() {
+            let local = RawConfig {
+                paths: Some(RawPaths {
+                    include: Some(vec!["src/".to_string()]),
+                    exclude: Some(vec!["!target/keep.rs".to_string()]),
+                }),
+                ..Default::default()
+            };
+
+            let global = RawConfig {
+                paths: Some(RawPaths {
+                    include: None,
+                    exclude: Some(vec!["target/".to_string()]),
+                }),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                Some(RawPaths {
+                    include: Some(vec!["src/".to_string()]),
+                    exclude: Some(vec!["target/".to_string(), "!target/keep.rs".to_string()]),
+                }),
+                RawConfig::merge(global, local).paths
+            )
+        }
+
+        #[test]
+        fn merge_grammar() {
             let local = RawConfig {
-                ignore_mode: Some(IgnoreMode::Blacklist),
-                patterns: Some(vec!["123".to_string(), "456".to_string()]),
+                grammar: Some(RawGrammar {
+                    unreported: Some("{prefix} @{keyword} {title}".to_string()),
+                    reported: None,
+                }),
                 ..Default::default()
             };
 
             let global = RawConfig {
-                ignore_mode: Some(IgnoreMode::Blacklist),
-                patterns: Some(vec!["789".to_string()]),
+                grammar: Some(RawGrammar {
+                    unreported: Some("{prefix} {keyword} - {title}".to_string()),
+                    reported: Some("{prefix} {keyword} [{issue}] - {title}".to_string()),
+                }),
                 ..Default::default()
             };
 
+            assert_eq!(
+                Some(RawGrammar {
+                    unreported: Some("{prefix} @{keyword} {title}".to_string()),
+                    reported: Some("{prefix} {keyword} [{issue}] - {title}".to_string()),
+                }),
+                RawConfig::merge(global, local).grammar
+            )
+        }
+
+        #[test]
+        fn merge_backend_sections() {
+            let global: RawConfig =
+                serde_yaml::from_str("backends:\n  Command:\n    create: a\n  Redmine: {}")
+                    .unwrap();
+            let local: RawConfig =
+                serde_yaml::from_str("backends:\n  Command:\n    create: b").unwrap();
+
+            let backends = RawConfig::merge(global, local).backends.unwrap();
+
+            assert_eq!(
+                vec!["Command", "Redmine"],
+                backends.keys().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                Some("b"),
+                backends["Command"].get("create").and_then(Value::as_str)
+            )
+        }
+    }
+
+    #[test]
+    fn parse_backends() {
+        let raw: RawConfig = serde_yaml::from_str("backend: Redmine").unwrap();
+
+        assert_eq!(Some(Backend::Custom("Redmine".to_owned())), raw.backend);
+        assert_eq!(
+            Some(Backend::Jira),
+            RawConfig::from_override("backend", "Jira").unwrap().backend
+        );
+        assert_eq!(
+            Value::String("Github".to_owned()),
+            serde_yaml::to_value(&Backend::Github).unwrap()
+        )
+    }
+
+    #[test]
+    fn undeclared_backends() {
+        let raw: RawConfig = serde_yaml::from_str("backend: Gitae").unwrap();
+
+        assert!(matches!(
+            raw.check_backends(),
+            Err(ConfigError::UndeclaredBackend(name)) if name == "Gitae"
+        ));
+
+        let raw: RawConfig = serde_yaml::from_str(
+            "backend: Gitea
+routes:
+  - name: ops
+    paths: [ops]
+    backend: Redmine
+backends:
+  Redmine: {}",
+        )
+        .unwrap();
+
+        assert!(raw.check_backends().is_ok())
+    }
+
+    #[test]
+    fn parse_keywords() {
+        let raw: RawConfig = serde_yaml::from_str(
+            "keywords:\n  - TODO\n  - name: FIXME\n    case_sensitive: false\n    aliases: [FIX]\n    labels: [bug]\n    repo: other",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(vec![
+                RawKeyword::Name("TODO".to_string()),
+                RawKeyword::Options(Box::new(RawKeywordOptions {
+                    name: "FIXME".to_string(),
+                    case_sensitive: Some(false),
+                    aliases: Some(vec!["FIX".to_string()]),
+                    labels: Some(vec!["bug".to_string()]),
+                    repo: Some("other".to_string()),
+                    ..Default::default()
+                })),
+            ]),
+            raw.keywords
+        )
+    }
+
+    mod parse {
+        use super::*;
+
+        #[test]
+        fn parse_override() {
             assert_eq!(
                 RawConfig {
-                    ignore_mode: Some(IgnoreMode::Blacklist),
-                    patterns: Some(vec![
-                        "123".to_string(),
-                        "456".to_string(),
-                        "789".to_string()
-                    ]),
-                    keywords: Some(vec!["TODO".to_string()]),
+                    user: Some("me".to_owned()),
                     ..Default::default()
                 },
-                RawConfig::merge(global, local)
+                RawConfig::from_override("user", "me").unwrap()
+            );
+            assert_eq!(
+                Some(vec![RawKeyword::Name("BUG".to_owned())]),
+                RawConfig::from_override("keywords", "[BUG]")
+                    .unwrap()
+                    .keywords
+            );
+            assert_eq!(
+                Some("{prefix} {title}".to_owned()),
+                RawConfig::from_override("grammar.unreported", "'{prefix} {title}'")
+                    .unwrap()
+                    .grammar
+                    .unwrap()
+                    .unreported
+            );
+            assert!(RawConfig::from_override("usr", "me").is_err())
+        }
+
+        #[test]
+        fn parse_empty() {
+            assert_eq!(RawConfig::default(), RawConfig::from_str("").unwrap());
+            assert_eq!(
+                RawConfig::default(),
+                RawConfig::from_str("# Nothing here\n").unwrap()
             )
         }
+
+        #[test]
+        fn parse_unknown_field() {
+            let err = RawConfig::from_str("keywords: [TODO]\nkeyword: [FIXME]").unwrap_err();
+
+            assert!(err.to_string().contains("unknown field `keyword`"));
+            assert_eq!(2, err.location().unwrap().line())
+        }
+
+        #[test]
+        fn parse_removed_keys() {
+            let err = RawConfig::from_str("ignore_mode: Whitelist\npatterns: [src]").unwrap_err();
+
+            assert!(err.to_string().contains("`paths.exclude`"));
+            assert!(RawConfig::from_override("patterns", "[target]")
+                .unwrap_err()
+                .to_string()
+                .contains("`paths.exclude`"))
+        }
+
+        #[test]
+        fn parse_unknown_keyword_option() {
+            let err =
+                RawConfig::from_str("keywords:\n  - name: BUG\n    lables: [bug]").unwrap_err();
+
+            assert!(err.to_string().contains("unknown field `lables`"))
+        }
+
+        #[test]
+        fn parse_syntax_error() {
+            let err = RawConfig::from_str("keywords: [TODO").unwrap_err();
+
+            assert!(err.location().is_some())
+        }
+
+        #[test]
+        fn parse_adversarial() {
+            let inputs = [
+                "keywords: 5",
+                "keywords: [{name: [a]}]",
+                "keywords: &k [*k]",
+                "keywords: [*missing]",
+                "concurrency: -1",
+                "max_file_size: 99999999999999999999999",
+                "backend: {name: Github}",
+                "token: {env: A, file: b}",
+                "{{{",
+                "- - -",
+                ":",
+                "\u{0}\u{7f}",
+            ];
+
+            for input in inputs.iter() {
+                assert!(RawConfig::from_str(input).is_err(), "{:?}", input)
+            }
+        }
     }
 }
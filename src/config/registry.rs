@@ -0,0 +1,425 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_yaml::Value;
+
+use super::{policy::Target, raw::Backend, ConfigError};
+use crate::{
+    api::{
+        command::Command,
+        gitea::Gitea,
+        github::Github,
+        jira::{Flavor, Jira},
+        scheduler::Scheduler,
+        Api,
+    },
+    Result,
+};
+
+/// Creates the api of a backend, registered by the name used as `backend` in the config
+#[async_trait]
+pub trait Connect: Send + Sync {
+    async fn connect(&self, connection: Connection<'_>) -> Result<Box<dyn Api>>;
+}
+
+/// Everything a backend is created from
+pub struct Connection<'a> {
+    pub target: &'a Target,
+    /// Already read from its source, if the config has one
+    pub token: Option<String>,
+    /// Shared by all backends, every request should be sent through it
+    pub scheduler: Arc<Scheduler>,
+    section: Option<&'a Value>,
+}
+
+impl<'a> Connection<'a> {
+    pub fn new(
+        target: &'a Target,
+        token: Option<String>,
+        scheduler: Arc<Scheduler>,
+        section: Option<&'a Value>,
+    ) -> Self {
+        Connection {
+            target,
+            token,
+            scheduler,
+            section,
+        }
+    }
+
+    /// The section of the backend in `backends`, missing if the config has none
+    pub fn section<T: DeserializeOwned>(&self) -> std::result::Result<Option<T>, ConfigError> {
+        parse_section(&self.target.backend, self.section)
+    }
+
+    pub fn required_token(&self) -> std::result::Result<String, ConfigError> {
+        self.token
+            .clone()
+            .ok_or_else(|| ConfigError::MissingValue("token".to_owned()))
+    }
+
+    pub fn required_url(&self) -> std::result::Result<&'a str, ConfigError> {
+        self.target
+            .instance_url()
+            .ok_or_else(|| ConfigError::MissingValue("url".to_owned()))
+    }
+}
+
+/// The backends todos can be reported to, by name
+#[derive(Clone)]
+pub struct Registry {
+    backends: HashMap<String, Arc<dyn Connect>>,
+}
+
+impl Default for Registry {
+    /// Has all built-in backends
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+
+        registry.register("Gitea", GiteaConnect);
+        // Forgejo is a fork of Gitea, its api is treated as identical
+        registry.register("Forgejo", GiteaConnect);
+        registry.register("Github", GithubConnect);
+        registry.register("Jira", JiraConnect);
+        registry.register("Command", CommandConnect);
+
+        registry
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Registry {
+            backends: HashMap::new(),
+        }
+    }
+
+    /// Replaces a backend with the same name
+    pub fn register(&mut self, name: &str, backend: impl Connect + 'static) {
+        self.backends.insert(name.to_owned(), Arc::new(backend));
+    }
+
+    pub fn get(&self, name: &str) -> std::result::Result<&dyn Connect, ConfigError> {
+        self.backends
+            .get(name)
+            .map(|b| b.as_ref())
+            .ok_or_else(|| ConfigError::UnknownBackend(name.to_owned()))
+    }
+}
+
+struct GiteaConnect;
+
+#[async_trait]
+impl Connect for GiteaConnect {
+    async fn connect(&self, connection: Connection<'_>) -> Result<Box<dyn Api>> {
+        let url = format!(
+            "{}/api/v1",
+            connection.required_url()?.trim_end_matches('/')
+        );
+        let target = connection.target;
+
+        Ok(Box::new(
+            Gitea::new(
+                &url,
+                connection.required_token()?,
+                &target.user,
+                &target.repo,
+                connection.scheduler,
+            )
+            .await?,
+        ))
+    }
+}
+
+struct GithubConnect;
+
+#[async_trait]
+impl Connect for GithubConnect {
+    async fn connect(&self, connection: Connection<'_>) -> Result<Box<dyn Api>> {
+        let target = connection.target;
+
+        Ok(Box::new(
+            Github::new(
+                target.user.clone(),
+                target.repo.clone(),
+                connection.required_token()?,
+                connection.scheduler,
+            )
+            .await?,
+        ))
+    }
+}
+
+struct JiraConnect;
+
+#[async_trait]
+impl Connect for JiraConnect {
+    async fn connect(&self, connection: Connection<'_>) -> Result<Box<dyn Api>> {
+        let url = connection.required_url()?;
+        // The project and email of the section, unless a keyword or route sets repo and user
+        let target = connection.target;
+
+        Ok(Box::new(
+            Jira::new(
+                url,
+                Flavor::of(url),
+                target.repo.clone(),
+                target.user.clone(),
+                connection.required_token()?,
+                connection.scheduler,
+            )
+            .await?,
+        ))
+    }
+}
+
+fn parse_section<T: DeserializeOwned>(
+    backend: &Backend,
+    section: Option<&Value>,
+) -> std::result::Result<Option<T>, ConfigError> {
+    section
+        .map(|section| serde_yaml::from_value(section.clone()))
+        .transpose()
+        .map_err(|e| ConfigError::Section(backend.name().to_owned(), e.to_string()))
+}
+
+/// Where a built-in backend reports to, if a keyword or route doesn't say otherwise
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct SectionTarget {
+    pub user: Option<String>,
+    pub repo: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Reads the section of a built-in backend,
+/// other backends read their section themselves when they connect
+pub(super) fn section_target(
+    backend: &Backend,
+    section: Option<&Value>,
+) -> std::result::Result<SectionTarget, ConfigError> {
+    Ok(match backend {
+        Backend::Gitea | Backend::Forgejo => {
+            parse_section::<GiteaSection>(backend, section)?.map(|s| SectionTarget {
+                user: s.user,
+                repo: s.repo,
+                url: s.url,
+            })
+        }
+        Backend::Github => {
+            parse_section::<GithubSection>(backend, section)?.map(|s| SectionTarget {
+                user: s.user,
+                repo: s.repo,
+                url: None,
+            })
+        }
+        Backend::Jira => parse_section::<JiraSection>(backend, section)?.map(|s| SectionTarget {
+            user: s.email,
+            repo: s.project,
+            url: s.url,
+        }),
+        Backend::Command | Backend::Custom(_) => None,
+    }
+    .unwrap_or_default())
+}
+
+/// The section of Gitea and Forgejo
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GiteaSection {
+    pub user: Option<String>,
+    pub repo: Option<String>,
+    pub url: Option<String>,
+}
+
+/// The section of Github
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GithubSection {
+    pub user: Option<String>,
+    pub repo: Option<String>,
+}
+
+/// The section of Jira
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JiraSection {
+    /// Of the account, only needed by Jira Cloud
+    pub email: Option<String>,
+    /// Key of the project
+    pub project: Option<String>,
+    pub url: Option<String>,
+}
+
+/// The section of the Command backend
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSection {
+    /// Gets the issue as JSON on stdin and prints the id of the created issue
+    pub create: String,
+    /// Gets the issue ids on stdin, one per line, and prints the closed ones
+    pub closed: Option<String>,
+}
+
+struct CommandConnect;
+
+#[async_trait]
+impl Connect for CommandConnect {
+    async fn connect(&self, connection: Connection<'_>) -> Result<Box<dyn Api>> {
+        let section: CommandSection = connection
+            .section()?
+            .ok_or_else(|| ConfigError::MissingValue("backends.Command".to_owned()))?;
+
+        // The commands can authenticate themselves, so the token is optional
+        Ok(Box::new(Command::new(
+            section.create,
+            section.closed,
+            connection.token,
+            connection.scheduler,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(backend: Backend) -> Target {
+        Target {
+            backend,
+            user: String::new(),
+            repo: String::new(),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn builtin_backends() {
+        let registry = Registry::default();
+
+        for name in ["Gitea", "Forgejo", "Github", "Jira", "Command"].iter() {
+            assert!(registry.get(name).is_ok())
+        }
+
+        assert!(matches!(
+            registry.get("Redmine"),
+            Err(ConfigError::UnknownBackend(name)) if name == "Redmine"
+        ))
+    }
+
+    #[tokio::test]
+    async fn register_custom_backend() {
+        struct Redmine;
+
+        #[async_trait]
+        impl Connect for Redmine {
+            async fn connect(&self, connection: Connection<'_>) -> Result<Box<dyn Api>> {
+                let section: CommandSection = connection.section()?.unwrap();
+
+                Ok(Box::new(Command::new(
+                    section.create,
+                    None,
+                    None,
+                    connection.scheduler,
+                )))
+            }
+        }
+
+        let mut registry = Registry::empty();
+        registry.register("Redmine", Redmine);
+
+        let target = target(Backend::Custom("Redmine".to_owned()));
+        let section = serde_yaml::from_str("create: echo 1").unwrap();
+        let connection = Connection::new(
+            &target,
+            None,
+            Arc::new(Scheduler::new(1, 0)),
+            Some(&section),
+        );
+
+        assert!(registry
+            .get("Redmine")
+            .unwrap()
+            .connect(connection)
+            .await
+            .is_ok())
+    }
+
+    #[tokio::test]
+    async fn connect_forgejo() {
+        let _labels = mockito::mock("GET", "/api/v1/repos/me/forgejo/labels")
+            .match_query(mockito::Matcher::Any)
+            .with_body("[]")
+            .create();
+        let _milestones = mockito::mock("GET", "/api/v1/repos/me/forgejo/milestones")
+            .match_query(mockito::Matcher::Any)
+            .with_body("[]")
+            .create();
+        let issue = mockito::mock("GET", "/api/v1/repos/me/forgejo/issues/3")
+            .match_header("authorization", "token secret")
+            .with_body(r#"{"number": 3, "state": "closed", "pull_request": {"merged": true}}"#)
+            .create();
+
+        let target = Target {
+            backend: Backend::Forgejo,
+            user: "me".to_owned(),
+            repo: "forgejo".to_owned(),
+            url: Some(format!("{}/", mockito::server_url())),
+        };
+        let connection = Connection::new(
+            &target,
+            Some("secret".to_owned()),
+            Arc::new(Scheduler::new(1, 0)),
+            None,
+        );
+        let api = Registry::default()
+            .get("Forgejo")
+            .unwrap()
+            .connect(connection)
+            .await
+            .unwrap();
+        let states = api.issue_states(&[3.into()]).await.unwrap();
+
+        issue.assert();
+        assert_eq!(Some(&crate::IssueState::Merged), states.get(&3.into()))
+    }
+
+    #[test]
+    fn builtin_sections() {
+        let section = serde_yaml::from_str("project: PROJ\nemail: me@example.com").unwrap();
+
+        assert_eq!(
+            SectionTarget {
+                user: Some("me@example.com".to_owned()),
+                repo: Some("PROJ".to_owned()),
+                url: None,
+            },
+            section_target(&Backend::Jira, Some(&section)).unwrap()
+        );
+        assert!(matches!(
+            section_target(&Backend::Github, Some(&section)),
+            Err(ConfigError::Section(name, _)) if name == "Github"
+        ));
+        assert_eq!(
+            SectionTarget::default(),
+            section_target(&Backend::Gitea, None).unwrap()
+        )
+    }
+
+    #[tokio::test]
+    async fn invalid_section() {
+        let target = target(Backend::Command);
+        let section = serde_yaml::from_str("run: echo 1").unwrap();
+        let connection = Connection::new(
+            &target,
+            None,
+            Arc::new(Scheduler::new(1, 0)),
+            Some(&section),
+        );
+
+        assert!(matches!(
+            CommandConnect.connect(connection).await,
+            Err(crate::TodoError::Config(ConfigError::Section(name, _))) if name == "Command"
+        ))
+    }
+}
@@ -0,0 +1,594 @@
+use std::{
+    collections::{HashMap, HashSet},
+    iter::once,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use super::{
+    policy::{Policy, Target, GITHUB_URL},
+    raw::{Backend, RawConfig, RawKeywordOptions, RawRoute, RawToken},
+    registry::{section_target, Connection, Registry},
+    token::resolve_token,
+    ConfigError, Scope,
+};
+use crate::{
+    api::{
+        scheduler::{Scheduler, DEFAULT_CONCURRENCY, DEFAULT_RETRIES},
+        Api,
+    },
+    todo::{IssueLocation, Todo},
+    Result,
+};
+
+/// The backends the todos are reported to, each repo is connected when it's first used
+pub struct Remote {
+    /// Identified by the directory of the scope, the name of the route and the keyword
+    policies: HashMap<(PathBuf, Option<String>, String), Policy>,
+    routes: HashMap<PathBuf, Vec<(String, GlobSet)>>,
+    apis: HashMap<Target, Box<dyn Api>>,
+    /// The scopes whose labels were already created in a repo
+    labeled: HashSet<(PathBuf, Target)>,
+    /// Resolved tokens by backend, instance and source, so a command or prompt only runs once
+    tokens: HashMap<(String, Option<String>, RawToken), String>,
+    scheduler: Arc<Scheduler>,
+    registry: Registry,
+}
+
+impl Remote {
+    pub async fn connect(scopes: &[Scope], registry: &Registry) -> Result<Remote> {
+        let mut policies = HashMap::new();
+        let mut routes = HashMap::new();
+        // The limits are shared by all backends, so they're taken from the root
+        let root = &scopes[0].raw;
+        let scheduler = Arc::new(Scheduler::new(
+            root.concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+            root.retries.unwrap_or(DEFAULT_RETRIES),
+        ));
+
+        for scope in scopes {
+            let raw = &scope.raw;
+            let scope_routes = create_routes(raw)?;
+
+            for options in raw.keyword_options() {
+                let unrouted = once((None, None));
+                let routed = scope_routes
+                    .iter()
+                    .map(|(r, _)| (Some(r.name.clone()), Some(r)));
+
+                for (name, route) in unrouted.chain(routed) {
+                    let policy = create_policy(&options, raw, route)?;
+
+                    policies.insert((scope.dir.clone(), name, options.name.clone()), policy);
+                }
+            }
+
+            routes.insert(
+                scope.dir.clone(),
+                scope_routes
+                    .into_iter()
+                    .map(|(r, globs)| (r.name, globs))
+                    .collect(),
+            );
+        }
+
+        Ok(Remote {
+            policies,
+            routes,
+            apis: HashMap::new(),
+            labeled: HashSet::new(),
+            tokens: HashMap::new(),
+            scheduler,
+            registry: registry.clone(),
+        })
+    }
+
+    /// The policy new todos of a keyword are reported with,
+    /// the path is relative to the root and selects the route
+    pub fn policy(&self, scope: &Scope, path: &Path, kind: &str) -> Option<&Policy> {
+        let route = self.routes.get(&scope.dir).and_then(|routes| {
+            routes
+                .iter()
+                .find(|(_, globs)| globs.is_match(path))
+                .map(|(name, _)| name.clone())
+        });
+
+        self.policies
+            .get(&(scope.dir.clone(), route, kind.to_owned()))
+    }
+
+    /// The repo the issue of a reported todo is in, selected by its issue reference.
+    /// Issue urls are only resolved on hosts configured in the scope,
+    /// so the token is never sent to a host from a todo.
+    pub fn issue_target(
+        &self,
+        scope: &Scope,
+        todo: &Todo,
+    ) -> std::result::Result<Option<Target>, ConfigError> {
+        let policy = |route: Option<String>| {
+            self.policies
+                .get(&(scope.dir.clone(), route, todo.kind.clone()))
+        };
+
+        Ok(match todo.issue_location() {
+            IssueLocation::Default => policy(None).map(|p| p.target.clone()),
+            IssueLocation::Route(name) => policy(Some(name)).map(|p| p.target.clone()),
+            IssueLocation::Repo { user, repo } => policy(None).map(|p| Target {
+                user,
+                repo,
+                ..p.target.clone()
+            }),
+            IssueLocation::Url { base, user, repo } => {
+                let declared = self
+                    .policies
+                    .iter()
+                    .filter(|((dir, _, _), _)| *dir == scope.dir)
+                    .map(|(_, p)| &p.target)
+                    .find(|t| instance_base(t).is_some_and(|b| b == base.trim_end_matches('/')))
+                    .ok_or(ConfigError::UndeclaredHost(base))?;
+
+                Some(Target {
+                    user,
+                    repo,
+                    ..declared.clone()
+                })
+            }
+        })
+    }
+
+    /// Connects to the repo if it's the first time it's used.
+    /// With `create_labels` the labels of the keywords reported to it are created.
+    pub async fn connect_target(&mut self, scope: &Scope, target: &Target) -> Result<()> {
+        if !self.apis.contains_key(target) {
+            let token = self.token(target, &scope.raw)?;
+            let api = create_api(
+                &self.registry,
+                target,
+                &scope.raw,
+                token,
+                self.scheduler.clone(),
+            )
+            .await?;
+
+            self.apis.insert(target.clone(), api);
+        }
+
+        let key = (scope.dir.clone(), target.clone());
+
+        if scope.raw.create_labels.unwrap_or(false) && !self.labeled.contains(&key) {
+            let mut labels: Vec<_> = self
+                .policies
+                .iter()
+                .filter(|((dir, _, _), p)| *dir == scope.dir && p.target == *target && p.report)
+                .flat_map(|((_, _, kind), p)| p.labels(kind))
+                .collect();
+            // Routes can report the same keyword to the same repo
+            labels.sort_by(|a, b| a.name.cmp(&b.name));
+            labels.dedup_by(|a, b| a.name == b.name);
+
+            if let Some(api) = self.apis.get_mut(target) {
+                api.create_labels(&labels).await?;
+            }
+
+            self.labeled.insert(key);
+        }
+
+        Ok(())
+    }
+
+    fn token(&mut self, target: &Target, raw: &RawConfig) -> Result<Option<String>> {
+        let token = match raw.token {
+            Some(ref token) => token,
+            None => return Ok(None),
+        };
+        let key = (
+            target.backend.name().to_owned(),
+            target.instance_url().map(str::to_owned),
+            token.clone(),
+        );
+
+        if let Some(resolved) = self.tokens.get(&key) {
+            return Ok(Some(resolved.clone()));
+        }
+
+        let resolved = resolve_token(token, target)?;
+        self.tokens.insert(key, resolved.clone());
+
+        Ok(Some(resolved))
+    }
+
+    /// The repo has to be connected with `connect_target` first
+    pub fn api(&self, target: &Target) -> &dyn Api {
+        self.apis[target].as_ref()
+    }
+}
+
+async fn create_api(
+    registry: &Registry,
+    target: &Target,
+    raw: &RawConfig,
+    token: Option<String>,
+    scheduler: Arc<Scheduler>,
+) -> Result<Box<dyn Api>> {
+    let backend = registry.get(target.backend.name())?;
+    let section = raw
+        .backends
+        .as_ref()
+        .and_then(|b| b.get(target.backend.name()));
+
+    backend
+        .connect(Connection::new(target, token, scheduler, section))
+        .await
+}
+
+/// The url in front of `/<user>/<repo>/issues` in the issue urls of the target
+fn instance_base(target: &Target) -> Option<&str> {
+    match target.backend {
+        Backend::Github => Some(GITHUB_URL),
+        _ => target.instance_url().map(|url| url.trim_end_matches('/')),
+    }
+}
+
+/// Compiles the paths of every route
+pub(super) fn create_routes(
+    raw: &RawConfig,
+) -> std::result::Result<Vec<(RawRoute, GlobSet)>, ConfigError> {
+    raw.routes
+        .iter()
+        .flatten()
+        .map(|route| {
+            let mut builder = GlobSetBuilder::new();
+
+            for path in route.paths.iter() {
+                let path = path.trim_end_matches('/');
+
+                for pattern in [path.to_owned(), format!("{}/**", path)] {
+                    let glob = GlobBuilder::new(&pattern)
+                        .literal_separator(true)
+                        .build()
+                        .map_err(|e| ConfigError::Glob(e.to_string()))?;
+
+                    builder.add(glob);
+                }
+            }
+
+            let globs = builder
+                .build()
+                .map_err(|e| ConfigError::Glob(e.to_string()))?;
+
+            Ok((route.clone(), globs))
+        })
+        .collect()
+}
+
+/// The target of a keyword falls back to the route, the section of its backend
+/// and then to the global target for each missing field.
+/// Keywords with their own target aren't routed.
+pub(super) fn create_policy(
+    options: &RawKeywordOptions,
+    raw: &RawConfig,
+    route: Option<&RawRoute>,
+) -> std::result::Result<Policy, ConfigError> {
+    let own_target = options.backend.is_some()
+        || options.user.is_some()
+        || options.repo.is_some()
+        || options.url.is_some();
+    let route = route.filter(|_| !own_target);
+
+    let backend = options
+        .backend
+        .clone()
+        .or_else(|| route.and_then(|r| r.backend.clone()))
+        .or_else(|| raw.backend.clone())
+        .ok_or_else(|| ConfigError::MissingValue("backend".to_owned()))?;
+    let optional = || (!backend.needs_repo()).then(String::new);
+    let section = section_target(
+        &backend,
+        raw.backends.as_ref().and_then(|b| b.get(backend.name())),
+    )?;
+
+    let target = Target {
+        user: options
+            .user
+            .clone()
+            .or_else(|| route.and_then(|r| r.user.clone()))
+            .or(section.user)
+            .or_else(|| raw.user.clone())
+            .or_else(optional)
+            .ok_or_else(|| ConfigError::MissingValue("user".to_owned()))?,
+        repo: options
+            .repo
+            .clone()
+            .or_else(|| route.and_then(|r| r.repo.clone()))
+            .or(section.repo)
+            .or_else(|| raw.repo.clone())
+            .or_else(optional)
+            .ok_or_else(|| ConfigError::MissingValue("repo".to_owned()))?,
+        url: options
+            .url
+            .clone()
+            .or_else(|| route.and_then(|r| r.url.clone()))
+            .or(section.url)
+            .or_else(|| raw.url.clone()),
+        backend,
+    };
+
+    let mut policy = Policy::new(target);
+
+    policy.route = route.map(|r| r.name.clone());
+
+    policy.report = options.report.unwrap_or(policy.report);
+    policy.labels = options.labels.clone();
+    policy.label_color = options.label_color.clone();
+    policy.label_description = options.label_description.clone();
+    policy.milestone = options.milestone.clone();
+    policy.assignees = options.assignees.clone().unwrap_or_default();
+    policy.title = options.title.clone();
+    policy.issue_type = options.issue_type.clone();
+
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_target_fallback() {
+        let raw = RawConfig {
+            backend: Some(Backend::Github),
+            user: Some("user".to_owned()),
+            repo: Some("repo".to_owned()),
+            ..Default::default()
+        };
+
+        let options = RawKeywordOptions {
+            name: "NOTE".to_owned(),
+            report: Some(false),
+            repo: Some("notes".to_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Policy {
+                report: false,
+                ..Policy::new(Target {
+                    backend: Backend::Github,
+                    user: "user".to_owned(),
+                    repo: "notes".to_owned(),
+                    url: None,
+                })
+            },
+            create_policy(&options, &raw, None).unwrap()
+        )
+    }
+
+    #[test]
+    fn routed_policy() {
+        let raw = RawConfig {
+            backend: Some(Backend::Github),
+            user: Some("acme".to_owned()),
+            repo: Some("monorepo".to_owned()),
+            routes: Some(vec![RawRoute {
+                name: "billing".to_owned(),
+                paths: vec!["services/billing".to_owned()],
+                repo: Some("billing".to_owned()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let routes = create_routes(&raw).unwrap();
+        let (route, globs) = &routes[0];
+
+        assert!(globs.is_match("services/billing/src/main.rs"));
+        assert!(!globs.is_match("services/billing-old/main.rs"));
+
+        let todo = RawKeywordOptions {
+            name: "TODO".to_owned(),
+            ..Default::default()
+        };
+        let policy = create_policy(&todo, &raw, Some(route)).unwrap();
+
+        assert_eq!("billing", policy.target.repo);
+        assert_eq!("acme", policy.target.user);
+        assert_eq!(Some("billing".to_owned()), policy.route);
+
+        let note = RawKeywordOptions {
+            name: "NOTE".to_owned(),
+            repo: Some("notes".to_owned()),
+            ..Default::default()
+        };
+        let policy = create_policy(&note, &raw, Some(route)).unwrap();
+
+        assert_eq!("notes", policy.target.repo);
+        assert_eq!(None, policy.route)
+    }
+
+    #[tokio::test]
+    async fn issue_url_target() {
+        let raw = RawConfig::merge(
+            RawConfig::default(),
+            RawConfig {
+                backend: Some(Backend::Gitea),
+                user: Some("acme".to_owned()),
+                repo: Some("app".to_owned()),
+                url: Some("https://git.example.com/".to_owned()),
+                ..Default::default()
+            },
+        );
+        let scopes = [Scope::new(Path::new("/repo"), PathBuf::from("/repo"), raw).unwrap()];
+        let scope = &scopes[0];
+        let remote = Remote::connect(&scopes, &Registry::default())
+            .await
+            .unwrap();
+        let todo = |url: &str| Todo {
+            line: 1,
+            prefix: "//".to_owned(),
+            keyword: "TODO".to_owned(),
+            kind: "TODO".to_owned(),
+            tag: None,
+            title: "Something".to_owned(),
+            issue_id: Some(1.into()),
+            issue_repo: Some(url.to_owned()),
+            comments: vec![],
+        };
+
+        assert_eq!(
+            Some(Target {
+                backend: Backend::Gitea,
+                user: "octo".to_owned(),
+                repo: "cat".to_owned(),
+                url: Some("https://git.example.com/".to_owned()),
+            }),
+            remote
+                .issue_target(scope, &todo("https://git.example.com/octo/cat/issues"))
+                .unwrap()
+        );
+        // The token is never sent to a host that isn't configured
+        assert!(matches!(
+            remote.issue_target(scope, &todo("https://evil.example/x/y/issues")),
+            Err(ConfigError::UndeclaredHost(host)) if host == "https://evil.example"
+        ));
+        assert!(remote
+            .issue_target(scope, &todo("https://github.com/octo/cat/issues"))
+            .is_err());
+
+        let github = Target {
+            backend: Backend::Github,
+            user: "octo".to_owned(),
+            repo: "cat".to_owned(),
+            url: None,
+        };
+        let codeberg = Target {
+            backend: Backend::Forgejo,
+            ..github.clone()
+        };
+
+        assert_eq!(Some("https://github.com"), instance_base(&github));
+        assert_eq!(Some("https://codeberg.org"), instance_base(&codeberg))
+    }
+
+    #[tokio::test]
+    async fn connect_lazily() {
+        // The Command backend can't connect without its section
+        let raw = RawConfig::merge(
+            RawConfig::default(),
+            RawConfig {
+                backend: Some(Backend::Command),
+                ..Default::default()
+            },
+        );
+        let scopes = [Scope::new(Path::new("/repo"), PathBuf::from("/repo"), raw).unwrap()];
+        let mut remote = Remote::connect(&scopes, &Registry::default())
+            .await
+            .unwrap();
+        let target = remote
+            .policy(&scopes[0], Path::new("main.rs"), "TODO")
+            .unwrap()
+            .target
+            .clone();
+
+        assert!(remote.apis.is_empty());
+        assert!(remote.connect_target(&scopes[0], &target).await.is_err())
+    }
+
+    #[tokio::test]
+    async fn resolve_token_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let count = temp.path().join("count");
+        let yaml = format!(
+            "backend: Command\ntoken:\n  command: echo >> {}; echo secret\nbackends:\n  Command:\n    create: echo 1\nkeywords:\n  - name: TODO\n    repo: a\n  - name: FIXME\n    repo: b",
+            count.display()
+        );
+        let raw = RawConfig::merge(RawConfig::default(), serde_yaml::from_str(&yaml).unwrap());
+        let scopes = [Scope::new(Path::new("/repo"), PathBuf::from("/repo"), raw).unwrap()];
+        let mut remote = Remote::connect(&scopes, &Registry::default())
+            .await
+            .unwrap();
+
+        for keyword in ["TODO", "FIXME"].iter() {
+            let target = remote
+                .policy(&scopes[0], Path::new("main.rs"), keyword)
+                .unwrap()
+                .target
+                .clone();
+
+            remote.connect_target(&scopes[0], &target).await.unwrap();
+        }
+
+        assert_eq!(2, remote.apis.len());
+        assert_eq!("\n", std::fs::read_to_string(&count).unwrap())
+    }
+
+    #[test]
+    fn section_target_fallback() {
+        let raw: RawConfig = serde_yaml::from_str(
+            "backend: Jira\nuser: global\nbackends:\n  Jira:\n    url: https://team.atlassian.net\n    email: me@example.com\n    project: PROJ",
+        )
+        .unwrap();
+        let todo = RawKeywordOptions {
+            name: "TODO".to_owned(),
+            ..Default::default()
+        };
+        let target = create_policy(&todo, &raw, None).unwrap().target;
+
+        assert_eq!("me@example.com", target.user);
+        assert_eq!("PROJ", target.repo);
+        assert_eq!(Some("https://team.atlassian.net"), target.instance_url());
+
+        let ops = RawKeywordOptions {
+            name: "OPS".to_owned(),
+            repo: Some("OPS".to_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!("OPS", create_policy(&ops, &raw, None).unwrap().target.repo)
+    }
+
+    #[test]
+    fn invalid_route_glob() {
+        let raw = RawConfig {
+            routes: Some(vec![RawRoute {
+                name: "web".to_owned(),
+                paths: vec!["web/[".to_owned()],
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        assert!(matches!(create_routes(&raw), Err(ConfigError::Glob(_))))
+    }
+
+    #[test]
+    fn policy_missing_target() {
+        let options = RawKeywordOptions {
+            name: "TODO".to_owned(),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            create_policy(&options, &Default::default(), None),
+            Err(ConfigError::MissingValue(_))
+        ))
+    }
+
+    #[test]
+    fn command_without_repo() {
+        let options = RawKeywordOptions {
+            name: "TODO".to_owned(),
+            ..Default::default()
+        };
+        let raw = RawConfig {
+            backend: Some(Backend::Command),
+            ..Default::default()
+        };
+
+        let policy = create_policy(&options, &raw, None).unwrap();
+
+        assert_eq!(Backend::Command, policy.target.backend);
+        assert_eq!("", policy.target.repo)
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use subprocess::{Exec, Redirection};
+
+use super::{
+    policy::Target,
+    raw::{Backend, RawToken},
+    ConfigError,
+};
+
+/// Where the token is read from, if it isn't written in the config
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenSource {
+    /// Name of an environment variable
+    Env(String),
+    /// Shell command that prints the token
+    Command(String),
+    /// File that only contains the token
+    File(PathBuf),
+    /// Asks `git credential fill` for the password of the host
+    GitCredential,
+}
+
+pub fn resolve_token(token: &RawToken, target: &Target) -> Result<String, ConfigError> {
+    let token = match token {
+        RawToken::Plain(token) => token.clone(),
+        RawToken::Source(TokenSource::Env(name)) => std::env::var(name)
+            .map_err(|_| ConfigError::Token(format!("Environment variable {} isn't set", name)))?,
+        RawToken::Source(TokenSource::Command(command)) => run_command(command)?,
+        RawToken::Source(TokenSource::File(path)) => read_file(&expand_home(path))?,
+        RawToken::Source(TokenSource::GitCredential) => git_credential(target)?,
+    };
+
+    let token = token.trim();
+
+    if token.is_empty() {
+        Err(ConfigError::Token("Token is empty".to_owned()))
+    } else if token.contains(['\n', '\r']) {
+        Err(ConfigError::Token(
+            "Token has more than one line".to_owned(),
+        ))
+    } else {
+        Ok(token.to_owned())
+    }
+}
+
+fn run_command(command: &str) -> Result<String, ConfigError> {
+    let data = Exec::shell(command)
+        .stderr(Redirection::None)
+        .capture()
+        .map_err(|e| ConfigError::Token(format!("Can't run \"{}\": {}", command, e)))?;
+
+    if data.exit_status.success() {
+        Ok(data.stdout_str())
+    } else {
+        Err(ConfigError::Token(format!("\"{}\" failed", command)))
+    }
+}
+
+pub(super) fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ConfigError> {
+    check_permissions(path)?;
+
+    fs::read_to_string(path)
+        .map_err(|e| ConfigError::Token(format!("Can't read {}: {}", path.display(), e)))
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), ConfigError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs::metadata(path)
+        .map_err(|e| ConfigError::Token(format!("Can't read {}: {}", path.display(), e)))?;
+
+    if metadata.permissions().mode() & 0o077 != 0 {
+        Err(ConfigError::InsecureTokenFile(path.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), ConfigError> {
+    Ok(())
+}
+
+fn git_credential(target: &Target) -> Result<String, ConfigError> {
+    let (protocol, host) = credential_host(target)?;
+    let input = format!("protocol={}\nhost={}\n\n", protocol, host);
+
+    let data = Exec::cmd("git")
+        .arg("credential")
+        .arg("fill")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(input.as_str())
+        .stderr(Redirection::None)
+        .capture()
+        .map_err(|_| ConfigError::Token("Can't run git credential".to_owned()))?;
+
+    if data.exit_status.success() {
+        parse_credential(&data.stdout_str())
+            .ok_or_else(|| ConfigError::Token(format!("Git has no credential for {}", host)))
+    } else {
+        Err(ConfigError::Token(format!(
+            "Git has no credential for {}",
+            host
+        )))
+    }
+}
+
+fn credential_host(target: &Target) -> Result<(String, String), ConfigError> {
+    match target.backend {
+        Backend::Github => Ok(("https".to_owned(), "github.com".to_owned())),
+        _ => {
+            let url = target
+                .instance_url()
+                .ok_or_else(|| ConfigError::MissingValue("url".to_owned()))?;
+            let (protocol, rest) = url.split_once("://").unwrap_or(("https", url));
+            let host = rest.split('/').next().unwrap_or(rest);
+
+            Ok((protocol.to_owned(), host.to_owned()))
+        }
+    }
+}
+
+fn parse_credential(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|l| l.strip_prefix("password="))
+        .map(|p| p.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> Target {
+        Target {
+            backend: Backend::Gitea,
+            user: "user".to_owned(),
+            repo: "repo".to_owned(),
+            url: Some("http://localhost:3000/gitea".to_owned()),
+        }
+    }
+
+    #[test]
+    fn plain_token() {
+        assert_eq!(
+            "abc",
+            resolve_token(&RawToken::Plain(" abc\n".to_owned()), &target()).unwrap()
+        );
+        assert!(resolve_token(&RawToken::Plain("abc\ndef".to_owned()), &target()).is_err())
+    }
+
+    #[test]
+    fn env_token() {
+        std::env::set_var("TODO_TEST_TOKEN_ENV", "from-env");
+
+        let token = RawToken::Source(TokenSource::Env("TODO_TEST_TOKEN_ENV".to_owned()));
+
+        assert_eq!("from-env", resolve_token(&token, &target()).unwrap())
+    }
+
+    #[test]
+    fn command_token() {
+        let token = RawToken::Source(TokenSource::Command("echo from-command".to_owned()));
+
+        assert_eq!("from-command", resolve_token(&token, &target()).unwrap())
+    }
+
+    #[test]
+    fn failing_command() {
+        let token = RawToken::Source(TokenSource::Command("exit 1".to_owned()));
+
+        assert!(matches!(
+            resolve_token(&token, &target()),
+            Err(ConfigError::Token(_))
+        ))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("todo-token-{}", std::process::id()));
+        let token = RawToken::Source(TokenSource::File(path.clone()));
+
+        fs::write(&path, "from-file\n").unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(
+            resolve_token(&token, &target()),
+            Err(ConfigError::InsecureTokenFile(_))
+        ));
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!("from-file", resolve_token(&token, &target()).unwrap());
+
+        fs::remove_file(&path).unwrap()
+    }
+
+    #[test]
+    fn gitea_credential_host() {
+        assert_eq!(
+            ("http".to_owned(), "localhost:3000".to_owned()),
+            credential_host(&target()).unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_credential_output() {
+        assert_eq!(
+            Some("secret".to_owned()),
+            parse_credential("protocol=https\nhost=github.com\nusername=me\npassword=secret\n")
+        );
+        assert_eq!(None, parse_credential("protocol=https\n"))
+    }
+
+    #[test]
+    fn parse_sources() {
+        let parse = |yaml: &str| serde_yaml::from_str::<RawToken>(yaml).unwrap();
+
+        assert_eq!(RawToken::Plain("abc".to_owned()), parse("abc"));
+        assert_eq!(
+            RawToken::Source(TokenSource::Env("GITHUB_TOKEN".to_owned())),
+            parse("env: GITHUB_TOKEN")
+        );
+        assert_eq!(
+            RawToken::Source(TokenSource::Command("pass show github".to_owned())),
+            parse("command: pass show github")
+        );
+        assert_eq!(
+            RawToken::Source(TokenSource::GitCredential),
+            parse("git-credential")
+        )
+    }
+}
@@ -0,0 +1,710 @@
+use std::iter::once;
+
+use regex::{escape, Regex};
+use thiserror::Error;
+
+use crate::todo::{IssueId, Todo};
+
+pub const DEFAULT_UNREPORTED: &str = "{prefix} {keyword}: {title}";
+pub const DEFAULT_REPORTED: &str = "{prefix} {keyword}({issue}): {title}";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum GrammarError {
+    #[error("Template \"{0}\" has an unclosed placeholder!")]
+    Unclosed(String),
+
+    #[error("Template \"{0}\" uses unknown placeholder {{{1}}}!")]
+    UnknownPlaceholder(String, String),
+
+    #[error("Template \"{0}\" uses {{{1}}} more than once!")]
+    DuplicatePlaceholder(String, String),
+
+    #[error("Template \"{0}\" misses {{{1}}}!")]
+    MissingPlaceholder(String, String),
+
+    #[error("Template \"{0}\" can't contain {{{1}}}!")]
+    ForbiddenPlaceholder(String, String),
+
+    #[error("Can't compile template \"{0}\" with the keywords: {1}")]
+    Regex(String, String),
+
+    #[error("At least one keyword is needed!")]
+    NoKeywords,
+
+    #[error("Keywords can't be empty!")]
+    EmptyKeyword,
+}
+
+/// A keyword that starts a todo
+#[derive(Debug, PartialEq, Clone)]
+pub struct Keyword {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub case_sensitive: bool,
+}
+
+impl Keyword {
+    pub fn new(name: &str) -> Keyword {
+        Keyword {
+            name: name.to_owned(),
+            aliases: vec![],
+            case_sensitive: true,
+        }
+    }
+
+    fn spellings(&self) -> impl Iterator<Item = &String> {
+        once(&self.name).chain(self.aliases.iter())
+    }
+
+    /// Checks if the text found in a file is this keyword or one of its aliases
+    pub fn matches(&self, written: &str) -> bool {
+        if self.case_sensitive {
+            self.spellings().any(|s| s == written)
+        } else {
+            let written = written.to_lowercase();
+
+            self.spellings().any(|s| s.to_lowercase() == written)
+        }
+    }
+
+    fn pattern(&self) -> String {
+        self.spellings()
+            .map(|spelling| {
+                let mut pattern = if self.case_sensitive {
+                    escape(spelling)
+                } else {
+                    format!("(?i:{})", escape(spelling))
+                };
+
+                // Only whole words are keywords, so "TODO" doesn't match "MYTODO"
+                if spelling.starts_with(is_word_char) {
+                    pattern.insert_str(0, r"\b");
+                }
+
+                if spelling.ends_with(is_word_char) {
+                    pattern.push_str(r"\b");
+                }
+
+                pattern
+            })
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Placeholder {
+    Prefix,
+    Keyword,
+    Tag,
+    Issue,
+    Title,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Placeholder> {
+        match name {
+            "prefix" => Some(Placeholder::Prefix),
+            "keyword" => Some(Placeholder::Keyword),
+            "tag" => Some(Placeholder::Tag),
+            "issue" => Some(Placeholder::Issue),
+            "title" => Some(Placeholder::Title),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Placeholder::Prefix => "prefix",
+            Placeholder::Keyword => "keyword",
+            Placeholder::Tag => "tag",
+            Placeholder::Issue => "issue",
+            Placeholder::Title => "title",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// A single form of a todo like `{prefix} {keyword}: {title}`
+#[derive(Debug)]
+struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    fn parse(source: &str) -> Result<Template, GrammarError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{')
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}')
+                }
+                '{' => {
+                    let mut name = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(GrammarError::Unclosed(source.to_owned())),
+                        }
+                    }
+
+                    let placeholder = Placeholder::from_name(&name).ok_or_else(|| {
+                        GrammarError::UnknownPlaceholder(source.to_owned(), name.clone())
+                    })?;
+
+                    if parts.contains(&Part::Placeholder(placeholder)) {
+                        return Err(GrammarError::DuplicatePlaceholder(source.to_owned(), name));
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.split_off(0)))
+                    }
+
+                    parts.push(Part::Placeholder(placeholder))
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal))
+        }
+
+        Ok(Template {
+            source: source.to_owned(),
+            parts,
+        })
+    }
+
+    fn contains(&self, placeholder: Placeholder) -> bool {
+        self.parts.contains(&Part::Placeholder(placeholder))
+    }
+
+    fn require(&self, placeholders: &[Placeholder]) -> Result<(), GrammarError> {
+        match placeholders.iter().find(|p| !self.contains(**p)) {
+            Some(missing) => Err(GrammarError::MissingPlaceholder(
+                self.source.clone(),
+                missing.name().to_owned(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn forbid(&self, placeholder: Placeholder) -> Result<(), GrammarError> {
+        if self.contains(placeholder) {
+            Err(GrammarError::ForbiddenPlaceholder(
+                self.source.clone(),
+                placeholder.name().to_owned(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn regex(&self, keyword_pattern: &str, issue_keys: bool) -> Result<Regex, GrammarError> {
+        let pattern: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => escape(text),
+                Part::Placeholder(Placeholder::Prefix) => "(?P<prefix>.*)".to_owned(),
+                Part::Placeholder(Placeholder::Keyword) => {
+                    format!("(?P<keyword>{})", keyword_pattern)
+                }
+                Part::Placeholder(Placeholder::Tag) => "(?P<tag>.+?)".to_owned(),
+                // Numbers need a `#` or url in front, keys like `PROJ-12` only a route
+                Part::Placeholder(Placeholder::Issue) => format!(
+                    "(?:{}{})",
+                    concat!(
+                        r"(?:(?P<issue_url>https?://[^\s#]+/issues/)",
+                        r"|(?P<issue_repo>[\w.-]+(?:/[\w.-]+)?)?#)(?P<issue_id>\d+)"
+                    ),
+                    if issue_keys {
+                        r"|(?:(?P<key_route>[\w.-]+)#)?(?P<issue_key>[A-Z][A-Z0-9_]*-\d+)"
+                    } else {
+                        ""
+                    }
+                ),
+                Part::Placeholder(Placeholder::Title) => "(?P<title>.+)".to_owned(),
+            })
+            .collect();
+
+        Regex::new(&format!("^{}$", pattern))
+            .map_err(|e| GrammarError::Regex(self.source.clone(), e.to_string()))
+    }
+
+    fn render(&self, todo: &Todo) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Placeholder(Placeholder::Prefix) => todo.prefix.clone(),
+                Part::Placeholder(Placeholder::Keyword) => todo.keyword.clone(),
+                Part::Placeholder(Placeholder::Tag) => todo.tag.clone().unwrap_or_default(),
+                Part::Placeholder(Placeholder::Issue) => todo.issue_ref().unwrap_or_default(),
+                Part::Placeholder(Placeholder::Title) => todo.title.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Describes how unreported and reported todos look in the source code.
+///
+/// The same grammar is used for finding, marking and removing todos,
+/// so a todo is always written back in the form it was found in.
+#[derive(Debug)]
+pub struct Grammar {
+    keywords: Vec<Keyword>,
+    keyword_pattern: String,
+    unreported: Template,
+    reported: Template,
+    unreported_regex: Regex,
+    reported_regex: Regex,
+}
+
+impl Default for Grammar {
+    fn default() -> Self {
+        Grammar::new(
+            &[Keyword::new("TODO")],
+            DEFAULT_UNREPORTED,
+            DEFAULT_REPORTED,
+        )
+        .unwrap()
+    }
+}
+
+impl Grammar {
+    pub fn new(
+        keywords: &[Keyword],
+        unreported: &str,
+        reported: &str,
+    ) -> Result<Grammar, GrammarError> {
+        let unreported = Template::parse(unreported)?;
+        let reported = Template::parse(reported)?;
+
+        unreported.require(&[
+            Placeholder::Prefix,
+            Placeholder::Keyword,
+            Placeholder::Title,
+        ])?;
+        unreported.forbid(Placeholder::Issue)?;
+        reported.require(&[
+            Placeholder::Prefix,
+            Placeholder::Keyword,
+            Placeholder::Issue,
+            Placeholder::Title,
+        ])?;
+
+        if keywords.is_empty() {
+            return Err(GrammarError::NoKeywords);
+        }
+
+        if keywords
+            .iter()
+            .flat_map(Keyword::spellings)
+            .any(String::is_empty)
+        {
+            return Err(GrammarError::EmptyKeyword);
+        }
+
+        let keyword_pattern = keywords
+            .iter()
+            .map(Keyword::pattern)
+            .collect::<Vec<_>>()
+            .join("|");
+
+        Ok(Grammar {
+            keywords: keywords.to_vec(),
+            unreported_regex: unreported.regex(&keyword_pattern, false)?,
+            reported_regex: reported.regex(&keyword_pattern, false)?,
+            keyword_pattern,
+            unreported,
+            reported,
+        })
+    }
+
+    /// Also parses issue keys like `PROJ-12`, which are only used by Jira.
+    /// Otherwise `TODO(UTF-8): ...` would look like a reported todo.
+    pub fn with_issue_keys(mut self) -> Result<Grammar, GrammarError> {
+        self.unreported_regex = self.unreported.regex(&self.keyword_pattern, true)?;
+        self.reported_regex = self.reported.regex(&self.keyword_pattern, true)?;
+
+        Ok(self)
+    }
+
+    /// Parses a line in the reported or unreported form
+    pub fn parse_line(&self, text: &str) -> Option<Todo> {
+        self.reported_regex
+            .captures(text)
+            .or_else(|| self.unreported_regex.captures(text))
+            .map(|c| {
+                let keyword = c.name("keyword").unwrap().as_str();
+
+                Todo {
+                    prefix: c.name("prefix").unwrap().as_str().to_owned(),
+                    keyword: keyword.to_owned(),
+                    kind: self
+                        .keyword(keyword)
+                        .map(|k| k.name.clone())
+                        .unwrap_or_else(|| keyword.to_owned()),
+                    tag: c.name("tag").map(|m| m.as_str().to_owned()),
+                    title: c.name("title").unwrap().as_str().to_owned(),
+                    issue_id: c
+                        .name("issue_id")
+                        .or_else(|| c.name("issue_key"))
+                        .map(|m| IssueId::new(m.as_str())),
+                    issue_repo: c
+                        .name("issue_url")
+                        .or_else(|| c.name("issue_repo"))
+                        .or_else(|| c.name("key_route"))
+                        .map(|m| m.as_str().to_owned()),
+                    comments: vec![],
+
+                    // Line will be changed later
+                    line: 0,
+                }
+            })
+    }
+
+    /// Finds the configured keyword for the text found in a file
+    pub fn keyword(&self, written: &str) -> Option<&Keyword> {
+        self.keywords.iter().find(|k| k.matches(written))
+    }
+
+    pub fn unreported_view(&self, todo: &Todo) -> String {
+        self.unreported.render(todo)
+    }
+
+    pub fn reported_view(&self, todo: &Todo) -> Option<String> {
+        todo.issue_id.as_ref().map(|_| self.reported.render(todo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn todo(tag: Option<&str>, issue_id: Option<u32>) -> Todo {
+        Todo {
+            line: 0,
+            prefix: "//".to_owned(),
+            keyword: "TODO".to_owned(),
+            kind: "TODO".to_owned(),
+            tag: tag.map(|t| t.to_owned()),
+            title: "Something".to_owned(),
+            issue_id: issue_id.map(IssueId::from),
+            issue_repo: None,
+            comments: vec![],
+        }
+    }
+
+    #[test]
+    fn parse_template() {
+        let template = Template::parse("{prefix} {{{keyword}}}").unwrap();
+
+        assert_eq!(
+            vec![
+                Part::Placeholder(Placeholder::Prefix),
+                Part::Literal(" {".to_owned()),
+                Part::Placeholder(Placeholder::Keyword),
+                Part::Literal("}".to_owned()),
+            ],
+            template.parts
+        )
+    }
+
+    #[test]
+    fn parse_template_errors() {
+        assert_eq!(
+            GrammarError::Unclosed("{prefix".to_owned()),
+            Template::parse("{prefix").unwrap_err()
+        );
+        assert_eq!(
+            GrammarError::UnknownPlaceholder("{user}".to_owned(), "user".to_owned()),
+            Template::parse("{user}").unwrap_err()
+        );
+        assert_eq!(
+            GrammarError::DuplicatePlaceholder("{title}{title}".to_owned(), "title".to_owned()),
+            Template::parse("{title}{title}").unwrap_err()
+        )
+    }
+
+    #[test]
+    fn validate_templates() {
+        let keywords = vec![Keyword::new("TODO")];
+
+        assert_eq!(
+            GrammarError::MissingPlaceholder("{prefix} {keyword}".to_owned(), "title".to_owned()),
+            Grammar::new(&keywords, "{prefix} {keyword}", DEFAULT_REPORTED).unwrap_err()
+        );
+        assert_eq!(
+            GrammarError::ForbiddenPlaceholder(DEFAULT_REPORTED.to_owned(), "issue".to_owned()),
+            Grammar::new(&keywords, DEFAULT_REPORTED, DEFAULT_REPORTED).unwrap_err()
+        );
+        assert_eq!(
+            GrammarError::MissingPlaceholder(DEFAULT_UNREPORTED.to_owned(), "issue".to_owned()),
+            Grammar::new(&keywords, DEFAULT_UNREPORTED, DEFAULT_UNREPORTED).unwrap_err()
+        )
+    }
+
+    #[test]
+    fn default_grammar() {
+        let grammar = Grammar::default();
+
+        assert_eq!(
+            Some(todo(None, None)),
+            grammar.parse_line("// TODO: Something")
+        );
+        assert_eq!(
+            Some(todo(None, Some(42))),
+            grammar.parse_line("// TODO(#42): Something")
+        );
+        assert!(grammar.parse_line("// FIXME: Something").is_none())
+    }
+
+    #[test]
+    fn issue_repo() {
+        let grammar = Grammar::default();
+        let routed = Todo {
+            issue_repo: Some("billing".to_owned()),
+            ..todo(None, Some(12))
+        };
+
+        assert_eq!(
+            Some(&routed),
+            grammar
+                .parse_line("// TODO(billing#12): Something")
+                .as_ref()
+        );
+        assert_eq!(
+            Some("// TODO(billing#12): Something".to_owned()),
+            grammar.reported_view(&routed)
+        )
+    }
+
+    #[test]
+    fn cross_repo_issues() {
+        let grammar = Grammar::default();
+
+        for line in [
+            "// TODO(octo/cat#5): Something",
+            "// TODO(https://github.com/octo/cat/issues/5): Something",
+        ]
+        .iter()
+        {
+            let todo = grammar.parse_line(line).unwrap();
+
+            assert_eq!(Some(5.into()), todo.issue_id);
+            assert_eq!(Some(line.to_string()), grammar.reported_view(&todo))
+        }
+    }
+
+    #[test]
+    fn issue_keys() {
+        let grammar = Grammar::default().with_issue_keys().unwrap();
+
+        for (line, route) in [
+            ("// TODO(PROJ-12): Something", None),
+            ("// TODO(billing#PROJ-12): Something", Some("billing")),
+        ]
+        .iter()
+        {
+            let todo = grammar.parse_line(line).unwrap();
+
+            assert_eq!(Some(IssueId::new("PROJ-12")), todo.issue_id);
+            assert_eq!(route.map(String::from), todo.issue_repo);
+            assert_eq!(Some(line.to_string()), grammar.reported_view(&todo))
+        }
+
+        // A number without `#` isn't an issue
+        assert_eq!(None, grammar.parse_line("// TODO(12): Something"))
+    }
+
+    #[test]
+    fn issue_keys_without_jira() {
+        let grammar = Grammar::default();
+
+        assert_eq!(None, grammar.parse_line("// TODO(PROJ-12): Something"));
+        assert_eq!(
+            None,
+            grammar.parse_line("// TODO(UTF-8): support encodings")
+        )
+    }
+
+    #[test]
+    fn custom_grammar_with_tag() {
+        let grammar = Grammar::new(
+            &[Keyword::new("TODO")],
+            "{prefix} {keyword}({tag}): {title}",
+            "{prefix} {keyword}({tag}) [{issue}]: {title}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(todo(Some("john"), None)),
+            grammar.parse_line("// TODO(john): Something")
+        );
+        assert_eq!(
+            Some(todo(Some("john"), Some(7))),
+            grammar.parse_line("// TODO(john) [#7]: Something")
+        );
+        assert_eq!(
+            Some("// TODO(john) [#7]: Something".to_owned()),
+            grammar.reported_view(&todo(Some("john"), Some(7)))
+        )
+    }
+
+    #[test]
+    fn custom_grammar_with_dash() {
+        let grammar = Grammar::new(
+            &[Keyword::new("TODO")],
+            "{prefix} {keyword} - {title}",
+            "{prefix} {keyword} [{issue}] - {title}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(todo(None, None)),
+            grammar.parse_line("// TODO - Something")
+        );
+        assert_eq!(
+            "// TODO - Something".to_owned(),
+            grammar.unreported_view(&todo(None, Some(3)))
+        );
+        assert_eq!(
+            Some("// TODO [#3] - Something".to_owned()),
+            grammar.reported_view(&todo(None, Some(3)))
+        )
+    }
+
+    #[test]
+    fn no_keywords() {
+        assert_eq!(
+            GrammarError::NoKeywords,
+            Grammar::new(&[], DEFAULT_UNREPORTED, DEFAULT_REPORTED).unwrap_err()
+        );
+        assert_eq!(
+            GrammarError::EmptyKeyword,
+            Grammar::new(&[Keyword::new("")], DEFAULT_UNREPORTED, DEFAULT_REPORTED).unwrap_err()
+        )
+    }
+
+    #[test]
+    fn escaped_keywords() {
+        let grammar = Grammar::new(
+            &[Keyword::new("C++TODO"), Keyword::new("XXX?")],
+            DEFAULT_UNREPORTED,
+            DEFAULT_REPORTED,
+        )
+        .unwrap();
+
+        assert!(grammar.parse_line("// C++TODO: Something").is_some());
+        assert!(grammar.parse_line("// XXX?: Something").is_some());
+        assert!(grammar.parse_line("// XX: Something").is_none())
+    }
+
+    #[test]
+    fn hostile_keywords() {
+        let keywords: Vec<_> = ["(?P<title>", "\\", "[", "{keyword}", "ТОДО", "$1"]
+            .iter()
+            .map(|k| Keyword::new(k))
+            .collect();
+        let grammar = Grammar::new(
+            &keywords,
+            "{prefix} ^{keyword}$ {title}",
+            "{prefix} ^{keyword}$ ({issue}) {title}",
+        )
+        .unwrap();
+
+        for keyword in ["(?P<title>", "\\", "[", "{keyword}", "ТОДО", "$1"].iter() {
+            let todo = grammar
+                .parse_line(&format!("// ^{}$ Something", keyword))
+                .unwrap();
+
+            assert_eq!(*keyword, todo.keyword)
+        }
+
+        assert!(grammar.parse_line("// ^x$ Something").is_none())
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let grammar = Grammar::new(
+            &[Keyword::new("TODO")],
+            "{prefix}{keyword}: {title}",
+            "{prefix}{keyword}({issue}): {title}",
+        )
+        .unwrap();
+
+        assert!(grammar.parse_line("// TODO: Something").is_some());
+        assert!(grammar.parse_line("// MYTODO: Something").is_none())
+    }
+
+    #[test]
+    fn case_insensitive_keyword() {
+        let grammar = Grammar::new(
+            &[
+                Keyword::new("TODO"),
+                Keyword {
+                    name: "FIXME".to_owned(),
+                    aliases: vec![],
+                    case_sensitive: false,
+                },
+            ],
+            DEFAULT_UNREPORTED,
+            DEFAULT_REPORTED,
+        )
+        .unwrap();
+
+        assert!(grammar.parse_line("// todo: Something").is_none());
+
+        let todo = grammar.parse_line("// fixme: Something").unwrap();
+
+        assert_eq!("fixme", todo.keyword);
+        assert_eq!("FIXME", todo.kind)
+    }
+
+    #[test]
+    fn keyword_aliases() {
+        let grammar = Grammar::new(
+            &[Keyword {
+                name: "TODO".to_owned(),
+                aliases: vec!["@todo".to_owned(), "Todo".to_owned()],
+                case_sensitive: true,
+            }],
+            DEFAULT_UNREPORTED,
+            DEFAULT_REPORTED,
+        )
+        .unwrap();
+
+        let todo = grammar.parse_line("// @todo: Something").unwrap();
+
+        assert_eq!("@todo", todo.keyword);
+        assert_eq!("TODO", todo.kind);
+        assert_eq!(
+            Some("TODO"),
+            grammar.keyword("Todo").map(|k| k.name.as_str())
+        );
+        assert_eq!(None, grammar.keyword("todo"))
+    }
+}
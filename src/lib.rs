@@ -0,0 +1,61 @@
+//! Finds TODO comments in a project, reports them to an issue tracker
+//! and removes them once their issue is closed.
+//!
+//! ```no_run
+//! # async fn run() -> todo::Result<()> {
+//! let conf = todo::Config::new(todo::project::base_dir()?, &[])?;
+//!
+//! for file in todo::actions::scan(&conf)?.files {
+//!     println!("{}: {} todos", file.path.display(), file.todos.len())
+//! }
+//!
+//! let update = todo::actions::report(&conf, &mut conf.remote().await?).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Nothing is printed, every function returns what it found or changed.
+
+use std::io;
+
+use thiserror::Error;
+
+use actions::Update;
+use api::ApiError;
+use config::ConfigError;
+use project::ProjectError;
+
+pub use api::{Api, Issue, IssueState};
+pub use config::{registry::Registry, Config};
+pub use todo::{IssueId, Todo};
+
+pub mod actions;
+pub mod api;
+pub mod config;
+pub mod grammar;
+pub mod project;
+pub mod source;
+pub mod stats;
+pub mod todo;
+pub mod todo_parser;
+
+#[derive(Debug, Error)]
+pub enum TodoError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Project(#[from] ProjectError),
+
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Api(#[from] ApiError),
+
+    /// Contains what was reported before the first failure
+    #[error("{} todo(s) couldn't be reported", .0.failed.len())]
+    Report(Box<Update>),
+}
+
+pub type Result<T> = std::result::Result<T, TodoError>;
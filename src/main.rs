@@ -1,60 +1,140 @@
-use std::io;
+use std::{io, path::Path};
 
-use thiserror::Error;
-use tokio;
-
-use actions::{list_todos, purge_todos, report_todos, todo_files};
-use api::ApiError;
 use clap::ArgMatches;
 use cli::create_cli;
-use config::{Config, ConfigError};
-use project::{base_dir, ProjectError};
+use subprocess::{Exec, NullFile};
+use todo::{
+    actions::{self, Skipped, Update},
+    config::{
+        explain::{check_config, show_config},
+        ConfigError,
+    },
+    project::base_dir,
+    Config, Result, TodoError,
+};
 
-mod actions;
-mod api;
 mod cli;
-mod config;
-mod project;
-mod todo;
-mod todo_parser;
 
 extern crate clap;
 
-#[derive(Debug, Error)]
-pub enum TodoError {
-    #[error(transparent)]
-    Io(#[from] io::Error),
-
-    #[error(transparent)]
-    Project(#[from] ProjectError),
-
-    #[error(transparent)]
-    Config(#[from] ConfigError),
-
-    #[error(transparent)]
-    Api(#[from] ApiError),
-}
-
-type Result<T> = std::result::Result<T, TodoError>;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli_matches = create_cli();
     let root = base_dir()?;
+    let overrides: Vec<_> = cli_matches
+        .values_of("overrides")
+        .map(|v| v.map(str::to_owned).collect())
+        .unwrap_or_default();
+
+    // Has to work with a broken config
+    if let ("config", Some(sub_matches)) = cli_matches.subcommand() {
+        return match sub_matches.subcommand() {
+            ("check", _) => handle_check_config(&root, &overrides),
+            ("show", Some(show_matches)) => {
+                let dir = Path::new(show_matches.value_of("dir").unwrap_or(""));
+                print!("{}", show_config(&root, &overrides, dir)?);
+
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+    }
 
-    let conf = Config::default(root).await?;
+    let conf = Config::new(root, &overrides)?;
+
+    for path in conf.tracked_tokens() {
+        eprintln!(
+            "Warning: {} contains a token and is tracked by git, use a token source instead!",
+            path.display()
+        )
+    }
 
     match cli_matches.subcommand() {
         ("list", Some(sub_matches)) => handle_list_todos(&conf, sub_matches)?,
-        ("files", _) => todo_files(&conf),
-        ("report", _) => report_todos(&conf).await?,
-        ("purge", _) => purge_todos(&conf).await?,
+        ("files", Some(sub_matches)) => match sub_matches.value_of("explain") {
+            Some(path) => handle_explain_file(&conf, Path::new(path))?,
+            None => {
+                for path in actions::files(&conf)? {
+                    println!("{}", path.display())
+                }
+            }
+        },
+        ("stats", Some(sub_matches)) => {
+            let (stats, skipped) = actions::stats(&conf)?;
+            warn_skipped(&skipped);
+
+            if sub_matches.is_present("json") {
+                let json = serde_json::to_string_pretty(&stats).map_err(io::Error::from)?;
+                println!("{}", json)
+            } else {
+                print!("{}", stats)
+            }
+        }
+        ("report", _) => match actions::report(&conf, &mut conf.remote().await?).await {
+            Ok(update) => print_report(&conf, &update),
+            // The created issues are already written, so they're printed before the error
+            Err(TodoError::Report(update)) => {
+                print_report(&conf, &update);
+
+                for failed in &update.failed {
+                    eprintln!(
+                        "Error: Couldn't report {}:{}: {}",
+                        failed.path.display(),
+                        failed.todo.line,
+                        failed.error
+                    );
+                }
+
+                // The failures are already printed, the debug output of the update would be noise
+                std::process::exit(1)
+            }
+            Err(e) => return Err(e),
+        },
+        ("purge", _) => {
+            let update = actions::purge(&conf, &mut conf.remote().await?).await?;
+
+            for unchecked in &update.unchecked {
+                eprintln!(
+                    "Warning: Couldn't check {}:{}: {}",
+                    unchecked.path.display(),
+                    unchecked.todo.line,
+                    unchecked.reason
+                );
+            }
+
+            print_update(&conf, &update, "removed")
+        }
         _ => {}
     }
 
     Ok(())
 }
 
+fn handle_check_config(root: &Path, overrides: &[String]) -> Result<()> {
+    let problems = check_config(root, overrides)?;
+
+    for problem in problems.iter() {
+        eprintln!("{}", problem)
+    }
+
+    if problems.is_empty() {
+        println!("Config is valid");
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(problems.len()).into())
+    }
+}
+
+/// Prints why a path is searched or not
+fn handle_explain_file(conf: &Config, path: &Path) -> Result<()> {
+    let path = std::env::current_dir()?.join(path);
+    let relative = path.strip_prefix(&conf.root).unwrap_or(&path);
+
+    println!("{}: {}", relative.display(), conf.decide(&path));
+
+    Ok(())
+}
+
 fn handle_list_todos(conf: &Config, matches: &ArgMatches) -> Result<()> {
     let mut unreported = false;
     let mut reported = false;
@@ -72,5 +152,71 @@ fn handle_list_todos(conf: &Config, matches: &ArgMatches) -> Result<()> {
         unreported = true
     }
 
-    list_todos(conf, reported, unreported)
+    let scan = actions::scan(conf)?;
+    warn_skipped(&scan.skipped);
+
+    for file in &scan.files {
+        for todo in &file.todos {
+            if todo.issue_id.is_none() && unreported || todo.issue_id.is_some() && reported {
+                println!("{}:{}", file.path.display(), todo)
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_report(conf: &Config, update: &Update) {
+    // The issue was created, but the file was changed since it was read
+    for (path, todo) in &update.unmarked {
+        eprintln!(
+            "Warning: Couldn't mark {}:{} with {}",
+            path.display(),
+            todo.line,
+            todo.issue_ref().unwrap_or_default()
+        );
+    }
+
+    print_update(conf, update, "reported")
+}
+
+fn print_update(conf: &Config, update: &Update, action: &str) {
+    warn_skipped(&update.skipped);
+    add_to_git(&conf.root, update);
+
+    for changed in &update.changed {
+        println!(
+            "{}: {} {}",
+            changed.path.display(),
+            action,
+            changed.issues.join(", ")
+        )
+    }
+
+    match update.changed.len() {
+        0 => println!("No files changed"),
+        1 => println!("1 file changed"),
+        n => println!("{} files changed", n),
+    }
+}
+
+/// Stages the changed files, other changes in the repo are left alone
+fn add_to_git(root: &Path, update: &Update) {
+    if update.changed.is_empty() {
+        return;
+    }
+
+    let _ = Exec::cmd("git")
+        .arg("add")
+        .arg("--")
+        .args(&update.changed.iter().map(|c| &c.path).collect::<Vec<_>>())
+        .cwd(root)
+        .stdout(NullFile)
+        .join();
+}
+
+fn warn_skipped(skipped: &[Skipped]) {
+    for file in skipped {
+        eprintln!("Warning: Skipped {}: {}", file.path.display(), file.error)
+    }
 }
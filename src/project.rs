@@ -1,7 +1,9 @@
+use std::collections::HashMap;
 use std::fs::read_dir;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use subprocess::Exec;
+use subprocess::{Exec, NullFile, Redirection};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -10,6 +12,10 @@ pub enum ProjectError {
     NotInRepo,
     #[error("Cant find git on the system")]
     GitNotFound,
+    #[error("{0} isn't a directory!")]
+    NotADirectory(PathBuf),
+    #[error("Can't read directory {0}: {1}, skip it with paths.exclude!")]
+    ReadDir(PathBuf, io::Error),
 }
 
 pub fn base_dir() -> Result<PathBuf, ProjectError> {
@@ -28,33 +34,181 @@ pub fn base_dir() -> Result<PathBuf, ProjectError> {
     }
 }
 
-pub fn find_files<F>(root: &PathBuf, filter_fn: &F) -> Option<Vec<PathBuf>>
+pub fn find_files<F>(root: &PathBuf, filter_fn: &F) -> Result<Vec<PathBuf>, ProjectError>
 where
     F: Fn(&PathBuf) -> bool,
 {
-    if root.is_dir() {
-        let paths: Vec<_> = read_dir(root)
-            .unwrap()
-            .filter_map(|r| r.ok().map(|d| d.path()))
-            .filter(filter_fn)
-            .collect();
-
-        let mut output = Vec::new();
-
-        for path in paths {
-            if path.is_dir() {
-                output.append(&mut find_files(&path, filter_fn).unwrap())
-            } else {
-                output.push(path)
-            }
+    if !root.is_dir() {
+        return Err(ProjectError::NotADirectory(root.clone()));
+    }
+
+    let paths: Vec<_> = read_dir(root)
+        .map_err(|e| ProjectError::ReadDir(root.clone(), e))?
+        .filter_map(|r| r.ok().map(|d| d.path()))
+        .filter(filter_fn)
+        .collect();
+
+    let mut output = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            output.append(&mut find_files(&path, filter_fn)?)
+        } else {
+            output.push(path)
         }
+    }
+
+    Ok(output)
+}
+
+/// The `.todo.yml` files of the project, ignored files are skipped
+/// except for the one at the root, which often contains a token
+pub fn find_configs(root: &Path) -> Vec<PathBuf> {
+    let mut configs = vec![];
+    let root_config = root.join(".todo.yml");
+
+    if root_config.is_file() {
+        configs.push(root_config.clone())
+    }
+
+    let command = Exec::cmd("git")
+        .arg("ls-files")
+        .arg("--cached")
+        .arg("--others")
+        .arg("--exclude-standard")
+        .arg("*.todo.yml")
+        .cwd(root)
+        .stdout(Redirection::Pipe)
+        .stderr(NullFile);
+
+    if let Ok(data) = command.capture() {
+        configs.extend(
+            data.stdout_str()
+                .lines()
+                .map(|l| root.join(l))
+                .filter(|p| p.file_name().is_some_and(|n| n == ".todo.yml"))
+                .filter(|p| *p != root_config && p.is_file()),
+        )
+    }
+
+    configs
+}
+
+/// Checks if the file is committed or staged in its repository
+pub fn is_tracked(path: &Path) -> bool {
+    let mut command = Exec::cmd("git")
+        .arg("ls-files")
+        .arg("--error-unmatch")
+        .arg(path)
+        .stdout(NullFile)
+        .stderr(NullFile);
+
+    if let Some(dir) = path.parent() {
+        command = command.cwd(dir)
+    }
+
+    command.join().map(|s| s.success()).unwrap_or(false)
+}
+
+/// Who last changed a line and when
+#[derive(Debug, PartialEq, Clone)]
+pub struct Blame {
+    pub author: String,
+    /// Seconds since the unix epoch
+    pub time: u64,
+}
 
-        Some(output)
-    } else {
-        None
+/// The blame of every line by its number, empty if the file isn't tracked
+pub fn blame(path: &Path) -> HashMap<u32, Blame> {
+    let mut command = Exec::cmd("git")
+        .arg("blame")
+        .arg("--line-porcelain")
+        .arg("--")
+        .arg(path)
+        .stdout(Redirection::Pipe)
+        .stderr(NullFile);
+
+    if let Some(dir) = path.parent() {
+        command = command.cwd(dir)
+    }
+
+    match command.capture() {
+        Ok(data) if data.exit_status.success() => parse_blame(&data.stdout_str()),
+        _ => HashMap::new(),
     }
 }
 
-pub fn add_to_git() {
-    let _ = Exec::shell("git add -A").join();
+/// Every line has a header with its number, followed by the commit info and the line itself
+fn parse_blame(output: &str) -> HashMap<u32, Blame> {
+    let mut blames = HashMap::new();
+    let mut line = None;
+    let mut author = String::new();
+    let mut time = 0;
+
+    for text in output.lines() {
+        if let Some(value) = text.strip_prefix("author ") {
+            author = value.to_owned()
+        } else if let Some(value) = text.strip_prefix("author-time ") {
+            time = value.parse().unwrap_or(0)
+        } else if text.starts_with('\t') {
+            if let Some(line) = line.take() {
+                blames.insert(
+                    line,
+                    Blame {
+                        author: author.clone(),
+                        time,
+                    },
+                );
+            }
+        } else if line.is_none() {
+            // The header is the commit, the original and the final line number
+            line = text.split(' ').nth(2).and_then(|n| n.parse().ok())
+        }
+    }
+
+    blames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_porcelain() {
+        let output = "\
+4f1e0c3 1 1 2
+author Alice
+author-mail <alice@example.com>
+author-time 1600000000
+summary First
+filename src/main.rs
+\t// TODO: Something
+4f1e0c3 2 2
+author Alice
+author-mail <alice@example.com>
+author-time 1600000000
+summary First
+filename src/main.rs
+\tcode();
+0000000 5 3 1
+author Not Committed Yet
+author-time 1700000000
+filename src/main.rs
+\t// FIXME: Later
+";
+        let blames = parse_blame(output);
+
+        assert_eq!(3, blames.len());
+        assert_eq!(
+            Some(&Blame {
+                author: "Alice".to_owned(),
+                time: 1_600_000_000
+            }),
+            blames.get(&1)
+        );
+        assert_eq!(
+            Some("Not Committed Yet"),
+            blames.get(&3).map(|b| b.author.as_str())
+        )
+    }
 }
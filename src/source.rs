@@ -0,0 +1,446 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+/// Only the start of a file is checked for null bytes
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Files are validated in chunks, so they never have to fit in memory
+const CHUNK_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum SourceError {
+    #[error("binary file")]
+    Binary,
+
+    #[error("larger than {0} bytes")]
+    TooLarge(u64),
+
+    #[error("not valid {0}, configure an encoding")]
+    Invalid(&'static str),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// How files are read, configured per scope
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    pub max_size: Option<u64>,
+    /// Used for files without a BOM that aren't valid UTF-8
+    pub fallback: Option<&'static Encoding>,
+}
+
+/// How a file is encoded, so it can be read line by line and written back the same way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Source {
+    encoding: &'static Encoding,
+    bom: bool,
+}
+
+impl Source {
+    pub fn detect(path: &Path, options: &ReadOptions) -> Result<Source, SourceError> {
+        if let Some(max_size) = options.max_size {
+            if fs::metadata(path)?.len() > max_size {
+                return Err(SourceError::TooLarge(max_size));
+            }
+        }
+
+        let mut start = Vec::with_capacity(BINARY_CHECK_LEN);
+        File::open(path)?
+            .take(BINARY_CHECK_LEN as u64)
+            .read_to_end(&mut start)?;
+
+        Source::detect_from(&start, File::open(path)?, options)
+    }
+
+    /// `start` is the beginning of `content`
+    fn detect_from<R: Read>(
+        start: &[u8],
+        content: R,
+        options: &ReadOptions,
+    ) -> Result<Source, SourceError> {
+        if let Some((encoding, _)) = Encoding::for_bom(start) {
+            return Ok(Source {
+                encoding,
+                bom: true,
+            });
+        }
+
+        if start.contains(&0) {
+            // UTF-16 without a BOM is also valid UTF-8, so it's decided by the NUL bytes
+            return match options.fallback {
+                Some(encoding) if is_utf16(encoding) => Ok(Source {
+                    encoding,
+                    bom: false,
+                }),
+                _ => Err(SourceError::Binary),
+            };
+        }
+
+        match (is_utf8(content)?, options.fallback) {
+            (true, _) => Ok(Source {
+                encoding: UTF_8,
+                bom: false,
+            }),
+            (false, Some(encoding)) => Ok(Source {
+                encoding,
+                bom: false,
+            }),
+            (false, None) => Err(SourceError::Invalid(UTF_8.name())),
+        }
+    }
+
+    /// The decoded content of the file, without the BOM
+    pub fn reader(&self, path: &Path) -> io::Result<Box<dyn BufRead>> {
+        Ok(self.decode(File::open(path)?))
+    }
+
+    pub fn decode<'a, R: Read + 'a>(&self, input: R) -> Box<dyn BufRead + 'a> {
+        if self.encoding == UTF_8 && !self.bom {
+            Box::new(BufReader::new(input))
+        } else {
+            let decoder = DecodeReaderBytesBuilder::new()
+                .encoding(Some(self.encoding))
+                .utf8_passthru(true)
+                .strip_bom(true)
+                .build(input);
+
+            Box::new(BufReader::new(decoder))
+        }
+    }
+
+    /// Encodes everything written like the file was encoded, starting with the BOM
+    pub fn writer<'a, W: Write + 'a>(&self, mut output: W) -> io::Result<Box<dyn Write + 'a>> {
+        if self.bom {
+            output.write_all(&self.encode("\u{feff}"))?;
+        }
+
+        if self.encoding == UTF_8 {
+            Ok(Box::new(output))
+        } else {
+            Ok(Box::new(Encoder {
+                source: *self,
+                output,
+                pending: vec![],
+            }))
+        }
+    }
+
+    /// Streams the file through `edit` into a temporary file next to it,
+    /// which replaces the file only if `edit` changed something.
+    /// The permissions of the file are kept and symlinks are followed,
+    /// so the target is changed instead of the link being replaced.
+    pub fn rewrite<F>(&self, path: &Path, edit: F) -> io::Result<bool>
+    where
+        F: FnOnce(Box<dyn BufRead>, &mut dyn Write) -> io::Result<bool>,
+    {
+        let path = &fs::canonicalize(path)?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut temp = NamedTempFile::new_in(dir)?;
+
+        let changed = {
+            let mut output = self.writer(BufWriter::new(temp.as_file_mut()))?;
+            let changed = edit(self.reader(path)?, &mut output)?;
+            output.flush()?;
+
+            changed
+        };
+
+        if changed {
+            fs::set_permissions(temp.path(), fs::metadata(path)?.permissions())?;
+            temp.as_file().sync_all()?;
+            temp.persist(path).map_err(|e| e.error)?;
+        }
+
+        Ok(changed)
+    }
+
+    fn encode(&self, text: &str) -> Vec<u8> {
+        if is_utf16(self.encoding) {
+            encode_utf16(text, self.encoding)
+        } else {
+            self.encoding.encode(text).0.into_owned()
+        }
+    }
+}
+
+/// Takes UTF-8 and writes it in the encoding of the source
+struct Encoder<W> {
+    source: Source,
+    output: W,
+    /// The start of a character that was split between two writes
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+
+        // Validated above
+        let text = std::str::from_utf8(&self.pending[..valid]).unwrap();
+        self.output.write_all(&self.source.encode(text))?;
+        self.pending.drain(..valid);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+// encoding_rs can only decode UTF-16, encoding has to be done by hand
+fn encode_utf16(text: &str, encoding: &'static Encoding) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len() * 2);
+
+    for unit in text.encode_utf16() {
+        if encoding == UTF_16LE {
+            bytes.extend_from_slice(&unit.to_le_bytes())
+        } else {
+            bytes.extend_from_slice(&unit.to_be_bytes())
+        }
+    }
+
+    bytes
+}
+
+/// A character can be split between two chunks, so the incomplete end is carried over
+fn is_utf8<R: Read>(mut input: R) -> io::Result<bool> {
+    let mut buffer = vec![0; CHUNK_LEN];
+    let mut carried = 0;
+
+    loop {
+        let read = input.read(&mut buffer[carried..])?;
+        let filled = carried + read;
+
+        if read == 0 {
+            return Ok(carried == 0);
+        }
+
+        match std::str::from_utf8(&buffer[..filled]) {
+            Ok(_) => carried = 0,
+            Err(e) if e.error_len().is_none() => {
+                buffer.copy_within(e.valid_up_to()..filled, 0);
+                carried = filled - e.valid_up_to();
+            }
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::WINDOWS_1252;
+
+    use super::*;
+
+    fn detect(bytes: &[u8], options: &ReadOptions) -> Result<Source, SourceError> {
+        Source::detect_from(&bytes[..bytes.len().min(BINARY_CHECK_LEN)], bytes, options)
+    }
+
+    fn read_all(source: &Source, bytes: &[u8]) -> String {
+        let mut text = String::new();
+        source.decode(bytes).read_to_string(&mut text).unwrap();
+
+        text
+    }
+
+    fn write_all(source: &Source, text: &str) -> Vec<u8> {
+        let mut bytes = vec![];
+        source
+            .writer(&mut bytes)
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn decode_utf8() {
+        let bytes = b"// TODO: Something";
+        let source = detect(bytes, &Default::default()).unwrap();
+
+        assert_eq!("// TODO: Something", read_all(&source, bytes));
+        assert_eq!(
+            b"// TODO: Other".to_vec(),
+            write_all(&source, "// TODO: Other")
+        )
+    }
+
+    #[test]
+    fn keep_utf8_bom() {
+        let bytes = b"\xEF\xBB\xBF// TODO: Something";
+        let source = detect(bytes, &Default::default()).unwrap();
+
+        assert_eq!("// TODO: Something", read_all(&source, bytes));
+        assert_eq!(
+            b"\xEF\xBB\xBF// TODO(#1): Something".to_vec(),
+            write_all(&source, "// TODO(#1): Something")
+        )
+    }
+
+    #[test]
+    fn utf16_with_bom() {
+        let bytes = b"\xFF\xFE/\x00/\x00 \x00\xE4\x00";
+        let source = detect(bytes, &Default::default()).unwrap();
+
+        assert_eq!("// ä", read_all(&source, bytes));
+
+        assert_eq!(bytes.to_vec(), write_all(&source, "// ä"))
+    }
+
+    #[test]
+    fn utf16_fallback_without_bom() {
+        // Only ASCII, so it's valid UTF-8 as well
+        let bytes = b"/\x00/\x00 \x00T\x00O\x00D\x00O\x00";
+        let options = ReadOptions {
+            fallback: Some(UTF_16LE),
+            ..Default::default()
+        };
+        let source = detect(bytes, &options).unwrap();
+
+        assert_eq!(UTF_16LE, source.encoding);
+        assert_eq!("// TODO", read_all(&source, bytes));
+        assert_eq!(bytes.to_vec(), write_all(&source, "// TODO"))
+    }
+
+    #[test]
+    fn skip_binary() {
+        assert!(matches!(
+            detect(b"\x7FELF\x00\x01", &Default::default()),
+            Err(SourceError::Binary)
+        ))
+    }
+
+    #[test]
+    fn latin1_fallback() {
+        let bytes = b"// TODO: Gr\xFC\xDFe";
+
+        assert!(matches!(
+            detect(bytes, &Default::default()),
+            Err(SourceError::Invalid(_))
+        ));
+
+        let options = ReadOptions {
+            fallback: Some(WINDOWS_1252),
+            ..Default::default()
+        };
+        let source = detect(bytes, &options).unwrap();
+
+        assert_eq!("// TODO: Grüße", read_all(&source, bytes));
+        assert_eq!(bytes.to_vec(), write_all(&source, "// TODO: Grüße"))
+    }
+
+    #[test]
+    fn rewrite_only_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        fs::write(&path, b"// TODO: Something\r\n").unwrap();
+
+        let source = Source::detect(&path, &Default::default()).unwrap();
+        let unchanged = source
+            .rewrite(&path, |mut input, output| {
+                io::copy(&mut input, output)?;
+                Ok(false)
+            })
+            .unwrap();
+
+        assert!(!unchanged);
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+
+        let changed = source
+            .rewrite(&path, |_, output| {
+                output.write_all(b"// TODO(#1): Something\r\n")?;
+                Ok(true)
+            })
+            .unwrap();
+
+        assert!(changed);
+        assert_eq!(
+            "// TODO(#1): Something\r\n",
+            fs::read_to_string(&path).unwrap()
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewrite_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.sh");
+        fs::write(&path, b"# TODO: Something\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let source = Source::detect(&path, &Default::default()).unwrap();
+        source
+            .rewrite(&path, |_, output| {
+                output.write_all(b"# TODO(#1): Something\n")?;
+                Ok(true)
+            })
+            .unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o755, mode & 0o777)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewrite_symlink_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("shared");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("lib.rs"), b"// TODO: Something\n").unwrap();
+        let link = dir.path().join("lib.rs");
+        std::os::unix::fs::symlink("shared/lib.rs", &link).unwrap();
+
+        let source = Source::detect(&link, &Default::default()).unwrap();
+        source
+            .rewrite(&link, |_, output| {
+                output.write_all(b"// TODO(#1): Something\n")?;
+                Ok(true)
+            })
+            .unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            "// TODO(#1): Something\n",
+            fs::read_to_string(target.join("lib.rs")).unwrap()
+        );
+        assert_eq!(1, fs::read_dir(&target).unwrap().count())
+    }
+
+    #[test]
+    fn utf8_across_chunks() {
+        let mut bytes = vec![b'a'; CHUNK_LEN - 1];
+        bytes.extend_from_slice("ä".as_bytes());
+
+        assert!(is_utf8(&bytes[..]).unwrap());
+        assert!(!is_utf8(&bytes[..bytes.len() - 1]).unwrap())
+    }
+}
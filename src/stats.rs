@@ -0,0 +1,231 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{project::Blame, todo::Todo};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// How long ago the line of a todo was last changed
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Age {
+    Week,
+    Month,
+    HalfYear,
+    Year,
+    Older,
+}
+
+impl Age {
+    /// Lines changed in the future, e.g. by a wrong clock, are a week old
+    pub fn of(time: u64, now: u64) -> Age {
+        match now.saturating_sub(time) / DAY {
+            0..=6 => Age::Week,
+            7..=29 => Age::Month,
+            30..=181 => Age::HalfYear,
+            182..=364 => Age::Year,
+            _ => Age::Older,
+        }
+    }
+}
+
+impl Display for Age {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Age::Week => "< 1 week",
+            Age::Month => "< 1 month",
+            Age::HalfYear => "< 6 months",
+            Age::Year => "< 1 year",
+            Age::Older => ">= 1 year",
+        })
+    }
+}
+
+/// The number of todos, grouped in different ways
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct Stats {
+    pub total: usize,
+    pub reported: usize,
+    pub unreported: usize,
+    /// By the configured keyword, so aliases are counted as their keyword
+    pub keywords: BTreeMap<String, usize>,
+    /// By the directory of the file, relative to the root
+    pub directories: BTreeMap<String, usize>,
+    /// By the extension of the file, or its name if it has none
+    pub file_types: BTreeMap<String, usize>,
+    /// Todos in files that aren't tracked by git have no author and age
+    pub authors: BTreeMap<String, usize>,
+    pub ages: BTreeMap<Age, usize>,
+}
+
+impl Stats {
+    /// `path` is relative to the root and `now` in seconds since the unix epoch
+    pub fn add(&mut self, path: &Path, todo: &Todo, blame: Option<&Blame>, now: u64) {
+        self.total += 1;
+
+        if todo.issue_id.is_some() {
+            self.reported += 1
+        } else {
+            self.unreported += 1
+        }
+
+        let directory = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+            _ => ".".to_owned(),
+        };
+        let file_type = path
+            .extension()
+            .or_else(|| path.file_name())
+            .map(|t| t.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        *self.keywords.entry(todo.kind.clone()).or_default() += 1;
+        *self.directories.entry(directory).or_default() += 1;
+        *self.file_types.entry(file_type).or_default() += 1;
+
+        if let Some(blame) = blame {
+            *self.authors.entry(blame.author.clone()).or_default() += 1;
+            *self.ages.entry(Age::of(blame.time, now)).or_default() += 1;
+        }
+    }
+}
+
+/// Prints a table for every group, the largest groups come first
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} todos ({} reported, {} unreported)",
+            self.total, self.reported, self.unreported
+        )?;
+
+        let ages: Vec<_> = self.ages.iter().map(|(a, n)| (a.to_string(), *n)).collect();
+
+        table(f, "Keyword", by_count(&self.keywords))?;
+        table(f, "Directory", by_count(&self.directories))?;
+        table(f, "File type", by_count(&self.file_types))?;
+        table(f, "Author", by_count(&self.authors))?;
+        // Ages are kept in their order
+        table(f, "Age", ages)
+    }
+}
+
+fn by_count(groups: &BTreeMap<String, usize>) -> Vec<(String, usize)> {
+    let mut rows: Vec<_> = groups.iter().map(|(k, n)| (k.clone(), *n)).collect();
+    rows.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    rows
+}
+
+fn table(f: &mut fmt::Formatter<'_>, title: &str, rows: Vec<(String, usize)>) -> fmt::Result {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let width = rows
+        .iter()
+        .map(|(k, _)| k.chars().count())
+        .chain(Some(title.len()))
+        .max()
+        .unwrap_or_default();
+
+    writeln!(f)?;
+    writeln!(f, "{:width$}  Todos", title, width = width)?;
+
+    for (key, count) in rows {
+        writeln!(f, "{:width$}  {}", key, count, width = width)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::todo::IssueId;
+
+    fn todo(kind: &str, issue_id: Option<IssueId>) -> Todo {
+        Todo {
+            line: 1,
+            prefix: "//".to_owned(),
+            keyword: kind.to_owned(),
+            kind: kind.to_owned(),
+            tag: None,
+            title: "Something".to_owned(),
+            issue_id,
+            issue_repo: None,
+            comments: vec![],
+        }
+    }
+
+    fn blame(author: &str, days: u64) -> Blame {
+        Blame {
+            author: author.to_owned(),
+            time: 1000 * DAY - days * DAY,
+        }
+    }
+
+    #[test]
+    fn ages() {
+        let now = 1000 * DAY;
+
+        assert_eq!(Age::Week, Age::of(now - 6 * DAY, now));
+        assert_eq!(Age::Month, Age::of(now - 7 * DAY, now));
+        assert_eq!(Age::Older, Age::of(now - 365 * DAY, now));
+        assert_eq!(Age::Week, Age::of(now + DAY, now))
+    }
+
+    #[test]
+    fn add_todos() {
+        let mut stats = Stats::default();
+        let now = 1000 * DAY;
+
+        stats.add(
+            Path::new("src/main.rs"),
+            &todo("TODO", None),
+            Some(&blame("alice", 2)),
+            now,
+        );
+        stats.add(
+            Path::new("src/lib.rs"),
+            &todo("FIXME", Some(3.into())),
+            Some(&blame("bob", 400)),
+            now,
+        );
+        stats.add(Path::new("Makefile"), &todo("TODO", None), None, now);
+
+        assert_eq!((3, 1, 2), (stats.total, stats.reported, stats.unreported));
+        assert_eq!(Some(&2), stats.keywords.get("TODO"));
+        assert_eq!(Some(&2), stats.directories.get("src"));
+        assert_eq!(Some(&1), stats.directories.get("."));
+        assert_eq!(Some(&1), stats.file_types.get("Makefile"));
+        assert_eq!(2, stats.authors.len());
+        assert_eq!(Some(&1), stats.ages.get(&Age::Older));
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(1, json["ages"]["week"]);
+        assert_eq!(2, json["file_types"]["rs"])
+    }
+
+    #[test]
+    fn print_tables() {
+        let mut stats = Stats::default();
+        stats.add(
+            Path::new("main.rs"),
+            &todo("TODO", None),
+            Some(&blame("alice", 2)),
+            1000 * DAY,
+        );
+
+        let table = stats.to_string();
+
+        assert!(table.starts_with("1 todos (0 reported, 1 unreported)\n"));
+        assert!(table.contains("\nKeyword  Todos\nTODO     1\n"));
+        assert!(table.contains("\nAge       Todos\n< 1 week  1\n"))
+    }
+}
@@ -1,26 +1,33 @@
 use std::fmt::Display;
 
-use regex::escape;
+use crate::grammar::Grammar;
 
 #[derive(Debug, PartialEq)]
 pub struct Todo {
     pub line: u32,
     pub prefix: String,
     pub keyword: String,
+    /// The keyword as configured, with aliases and case resolved
+    pub kind: String,
+    pub tag: Option<String>,
     pub title: String,
-    pub issue_id: Option<u32>,
+    pub issue_id: Option<IssueId>,
+    /// Written before the issue id, if the issue isn't in the default repo.
+    /// Either the name of a route, `owner/repo` or an issue url without the id.
+    pub issue_repo: Option<String>,
     pub comments: Vec<String>,
 }
 
 impl Display for Todo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let issue_str = if let Some(ref issue) = self.issue_id {
-            format!("(#{})", issue)
-        } else {
-            String::new()
+        let annotation = match (&self.tag, self.issue_ref()) {
+            (Some(tag), Some(issue)) => format!("({}, {})", tag, issue),
+            (Some(tag), None) => format!("({})", tag),
+            (None, Some(issue)) => format!("({})", issue),
+            (None, None) => String::new(),
         };
 
-        let comments_str = if self.comments.len() == 0 {
+        let comments_str = if self.comments.is_empty() {
             String::new()
         } else {
             let mut out = String::from("\n  ");
@@ -29,44 +36,153 @@ impl Display for Todo {
             out
         };
 
-        write!(
+        writeln!(
             f,
-            "{}: {}{}: {}{}\n",
-            self.line, self.keyword, issue_str, self.title, comments_str
+            "{}: {}{}: {}{}",
+            self.line, self.keyword, annotation, self.title, comments_str
         )
     }
 }
 
 impl Todo {
-    pub fn reported_view(&self) -> Option<String> {
+    /// The issue as written in the comment, like `#12`, `billing#12`, `PROJ-12` or an url
+    pub fn issue_ref(&self) -> Option<String> {
         self.issue_id
-            .map(|i| format!("{} {}(#{}): {}", self.prefix, self.keyword, i, self.title))
+            .as_ref()
+            .map(|id| match self.issue_repo.as_deref() {
+                Some(url) if url.contains("://") => format!("{}{}", url, id),
+                // Keys like `PROJ-12` are written without the `#`
+                None if id.number().is_none() => id.to_string(),
+                repo => format!("{}#{}", repo.unwrap_or_default(), id),
+            })
     }
 
-    pub fn unreported_pattern(&self) -> String {
-        escape(&format!("{} {}: {}", self.prefix, self.keyword, self.title))
+    pub fn issue_location(&self) -> IssueLocation {
+        match self.issue_repo.as_deref() {
+            None => IssueLocation::Default,
+            Some(url) if url.contains("://") => {
+                parse_issue_url(url).unwrap_or_else(|| IssueLocation::Route(url.to_owned()))
+            }
+            Some(repo) => match repo.split_once('/') {
+                Some((user, repo)) => IssueLocation::Repo {
+                    user: user.to_owned(),
+                    repo: repo.to_owned(),
+                },
+                None => IssueLocation::Route(repo.to_owned()),
+            },
+        }
     }
 
-    pub fn reported_pattern(&self) -> Option<String> {
-        self.reported_view().map(|v| {
-            if self.comments.len() == 0 {
-                format!("{}(\r\n|\n)?", escape(&v))
-            } else {
-                let comments = self
-                    .comments
-                    .iter()
-                    .map(|c| {
-                        let mut with_pref = self.prefix.clone();
-                        with_pref.push_str(" ");
-                        with_pref.push_str(c);
+    pub fn reported_view(&self, grammar: &Grammar) -> Option<String> {
+        grammar.reported_view(self)
+    }
 
-                        escape(&with_pref)
-                    })
-                    .collect::<Vec<_>>()
-                    .join("(\r\n|\n)");
+    pub fn unreported_view(&self, grammar: &Grammar) -> String {
+        grammar.unreported_view(self)
+    }
 
-                format!("{}(\r\n|\n){}(\r\n|\n)?", escape(&v), comments)
+    /// The lines of the todo in the reported form, including the comments
+    pub fn reported_lines(&self, grammar: &Grammar) -> Option<Vec<String>> {
+        self.reported_view(grammar).map(|v| {
+            Some(v)
+                .into_iter()
+                .chain(self.comments.iter().map(|c| self.comment_line(c)))
+                .collect()
+        })
+    }
+
+    /// An empty comment is written as the prefix alone
+    pub fn comment_line(&self, comment: &str) -> String {
+        if comment.is_empty() {
+            self.prefix.clone()
+        } else {
+            format!("{} {}", self.prefix, comment)
+        }
+    }
+}
+
+/// The id of an issue, a number like `12` or a key like `PROJ-12`
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub struct IssueId(String);
+
+impl IssueId {
+    pub fn new(id: &str) -> IssueId {
+        IssueId(id.to_owned())
+    }
+
+    /// Only numbers and keys can be written into a todo, a leading `#` is skipped
+    pub fn parse(text: &str) -> Option<IssueId> {
+        let id = text.trim().trim_start_matches('#');
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        let is_key = match id.rsplit_once('-') {
+            Some((project, number)) => {
+                project.starts_with(|c: char| c.is_ascii_uppercase())
+                    && project
+                        .bytes()
+                        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+                    && is_number(number)
             }
+            None => false,
+        };
+
+        if is_number(id) || is_key {
+            Some(IssueId::new(id))
+        } else {
+            None
+        }
+    }
+
+    /// Github and Gitea only have numbered issues
+    pub fn number(&self) -> Option<u32> {
+        self.0.parse().ok()
+    }
+}
+
+impl From<u32> for IssueId {
+    fn from(number: u32) -> Self {
+        IssueId(number.to_string())
+    }
+}
+
+impl Display for IssueId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Where the issue of a reported todo lives
+#[derive(Debug, PartialEq)]
+pub enum IssueLocation {
+    /// The repo the policy of the todo reports to
+    Default,
+    /// A route of the config
+    Route(String),
+    /// Another repo on the same backend
+    Repo { user: String, repo: String },
+    /// Another repo on any backend, `base` is the url without user and repo
+    Url {
+        base: String,
+        user: String,
+        repo: String,
+    },
+}
+
+/// Splits `https://host/user/repo/issues/` into its parts
+fn parse_issue_url(url: &str) -> Option<IssueLocation> {
+    let (scheme, rest) = url.split_once("://")?;
+    let rest = rest.trim_end_matches('/').strip_suffix("/issues")?;
+    let mut parts: Vec<_> = rest.split('/').collect();
+
+    let repo = parts.pop()?;
+    let user = parts.pop()?;
+
+    if parts.is_empty() || user.is_empty() || repo.is_empty() {
+        None
+    } else {
+        Some(IssueLocation::Url {
+            base: format!("{}://{}", scheme, parts.join("/")),
+            user: user.to_owned(),
+            repo: repo.to_owned(),
         })
     }
 }
@@ -81,8 +197,11 @@ mod tests {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
             issue_id: None,
+            issue_repo: None,
             comments: vec![],
         };
 
@@ -95,22 +214,127 @@ mod tests {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
-            issue_id: Some(42),
+            issue_id: Some(42.into()),
+            issue_repo: None,
             comments: vec![],
         };
 
         assert_eq!("10: TODO(#42): Something\n", format!("{}", todo))
     }
 
+    #[test]
+    fn display_with_issue_key() {
+        let todo = Todo {
+            line: 10,
+            prefix: String::from("//"),
+            keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
+            title: String::from("Something"),
+            issue_id: Some(IssueId::new("PROJ-42")),
+            issue_repo: None,
+            comments: vec![],
+        };
+
+        assert_eq!("10: TODO(PROJ-42): Something\n", format!("{}", todo))
+    }
+
+    #[test]
+    fn parse_issue_ids() {
+        assert_eq!(Some(12.into()), IssueId::parse("#12\n"));
+        assert_eq!(Some(IssueId::new("PROJ_2-7")), IssueId::parse("PROJ_2-7"));
+        assert_eq!(None, IssueId::parse("created"));
+        assert_eq!(None, IssueId::parse("proj-7"));
+        assert_eq!(None, IssueId::parse(""))
+    }
+
+    #[test]
+    fn issue_locations() {
+        let location = |repo: Option<&str>| {
+            Todo {
+                line: 1,
+                prefix: String::from("//"),
+                keyword: String::from("TODO"),
+                kind: String::from("TODO"),
+                tag: None,
+                title: String::from("Something"),
+                issue_id: Some(5.into()),
+                issue_repo: repo.map(String::from),
+                comments: vec![],
+            }
+            .issue_location()
+        };
+
+        assert_eq!(IssueLocation::Default, location(None));
+        assert_eq!(
+            IssueLocation::Route(String::from("billing")),
+            location(Some("billing"))
+        );
+        assert_eq!(
+            IssueLocation::Repo {
+                user: String::from("octo"),
+                repo: String::from("cat"),
+            },
+            location(Some("octo/cat"))
+        );
+        assert_eq!(
+            IssueLocation::Url {
+                base: String::from("https://git.example.com/gitea"),
+                user: String::from("octo"),
+                repo: String::from("cat"),
+            },
+            location(Some("https://git.example.com/gitea/octo/cat/issues/"))
+        )
+    }
+
+    #[test]
+    fn display_with_issue_repo() {
+        let todo = Todo {
+            line: 10,
+            prefix: String::from("//"),
+            keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
+            title: String::from("Something"),
+            issue_id: Some(12.into()),
+            issue_repo: Some(String::from("billing")),
+            comments: vec![],
+        };
+
+        assert_eq!("10: TODO(billing#12): Something\n", format!("{}", todo))
+    }
+
+    #[test]
+    fn display_with_tag() {
+        let todo = Todo {
+            line: 10,
+            prefix: String::from("//"),
+            keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: Some(String::from("john")),
+            title: String::from("Something"),
+            issue_id: Some(42.into()),
+            issue_repo: None,
+            comments: vec![],
+        };
+
+        assert_eq!("10: TODO(john, #42): Something\n", format!("{}", todo))
+    }
+
     #[test]
     fn display_with_comments() {
         let todo = Todo {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
             issue_id: None,
+            issue_repo: None,
             comments: vec!["More".to_owned(), "And More".to_owned()],
         };
 
@@ -126,12 +350,15 @@ mod tests {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
             issue_id: None,
+            issue_repo: None,
             comments: vec!["More".to_owned(), "And More".to_owned()],
         };
 
-        assert_eq!(None, todo.reported_view())
+        assert_eq!(None, todo.reported_view(&Grammar::default()))
     }
 
     #[test]
@@ -140,14 +367,17 @@ mod tests {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
-            issue_id: Some(123),
+            issue_id: Some(123.into()),
+            issue_repo: None,
             comments: vec!["More".to_owned(), "And More".to_owned()],
         };
 
         assert_eq!(
             Some("// TODO(#123): Something".to_owned()),
-            todo.reported_view()
+            todo.reported_view(&Grammar::default())
         )
     }
 
@@ -157,12 +387,18 @@ mod tests {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
-            issue_id: Some(123),
+            issue_id: Some(123.into()),
+            issue_repo: None,
             comments: vec!["More".to_owned(), "And More".to_owned()],
         };
 
-        assert_eq!("// TODO: Something".to_owned(), todo.unreported_pattern())
+        assert_eq!(
+            "// TODO: Something".to_owned(),
+            todo.unreported_view(&Grammar::default())
+        )
     }
 
     #[test]
@@ -171,17 +407,21 @@ mod tests {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
-            issue_id: Some(123),
+            issue_id: Some(123.into()),
+            issue_repo: None,
             comments: vec!["More".to_owned(), "And More".to_owned()],
         };
 
         assert_eq!(
-            Some(
-                "// TODO\\(\\#123\\): Something(\r\n|\n)// More(\r\n|\n)// And More(\r\n|\n)?"
-                    .to_owned()
-            ),
-            todo.reported_pattern()
+            Some(vec![
+                "// TODO(#123): Something".to_owned(),
+                "// More".to_owned(),
+                "// And More".to_owned()
+            ]),
+            todo.reported_lines(&Grammar::default())
         )
     }
 
@@ -191,14 +431,17 @@ mod tests {
             line: 10,
             prefix: String::from("//"),
             keyword: String::from("TODO"),
+            kind: String::from("TODO"),
+            tag: None,
             title: String::from("Something"),
-            issue_id: Some(123),
+            issue_id: Some(123.into()),
+            issue_repo: None,
             comments: vec![],
         };
 
         assert_eq!(
-            Some("// TODO\\(\\#123\\): Something(\r\n|\n)?".to_owned()),
-            todo.reported_pattern()
+            Some(vec!["// TODO(#123): Something".to_owned()]),
+            todo.reported_lines(&Grammar::default())
         )
     }
 }
@@ -1,136 +1,452 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, BufRead, Write},
+};
+
+use crate::{grammar::Grammar, todo::Todo};
+
+/// Longer lines, like minified code, are skipped by default
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+/// Reads the todos of a file line by line, so only the current todo is kept in memory
+pub struct TodoReader<'a, R> {
+    grammar: &'a Grammar,
+    input: R,
+    buffer: String,
+    line: u32,
+    last_todo: Option<Todo>,
+    max_line_length: usize,
+}
 
-use regex::{escape, Captures, Regex};
+impl<'a, R: BufRead> TodoReader<'a, R> {
+    pub fn new(grammar: &'a Grammar, input: R) -> Self {
+        TodoReader {
+            grammar,
+            input,
+            buffer: String::new(),
+            line: 0,
+            last_todo: None,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+        }
+    }
 
-use crate::todo::Todo;
+    /// Lines with more bytes are skipped without being kept in memory
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
 
-const COMMENT_PATTERN: &str = "^({}) (?P<comment>.*)$|^({})$";
+    /// Reads the next line into the buffer, returns the number of bytes read
+    /// and whether the line was too long to be kept
+    fn read_line(&mut self) -> io::Result<(usize, bool)> {
+        let mut bytes = vec![];
+        let mut read = 0;
+        let mut too_long = false;
 
-// Memory usage is probably high on big files
-// As the will be completly loaded into ram
-pub fn find_todos(keywords: &[String], input: &str) -> Vec<Todo> {
-    let regex = todo_regex(keywords);
-    let mut last_todo: Option<Todo> = None;
-    let mut output: Vec<Todo> = vec![];
+        loop {
+            let available = self.input.fill_buf()?;
 
-    for (line, text) in input.lines().enumerate() {
-        let mut parsed = parse_line(&regex, &text);
+            if available.is_empty() {
+                break;
+            }
 
-        if let Some(ref mut new_todo) = parsed {
-            if let Some(todo) = last_todo {
-                output.push(todo);
+            let (len, done) = match available.iter().position(|b| *b == b'\n') {
+                Some(end) => (end + 1, true),
+                None => (available.len(), false),
+            };
+
+            too_long |= read + len > self.max_line_length;
+
+            if !too_long {
+                bytes.extend_from_slice(&available[..len]);
             }
 
-            new_todo.line = line as u32 + 1;
-            last_todo = parsed;
-        } else if let Some(ref mut todo) = last_todo {
-            let reg = Regex::new(&COMMENT_PATTERN.replace("{}", &escape(&todo.prefix))).unwrap();
-
-            if let Some(m) = reg.captures(&text) {
-                todo.comments.push(
-                    m.name("comment")
-                        .map(|m| m.as_str())
-                        .unwrap_or("")
-                        .to_owned(),
-                )
+            read += len;
+            self.input.consume(len);
+
+            if done {
+                break;
             }
         }
-    }
 
-    if let Some(todo) = last_todo {
-        output.push(todo)
+        if !too_long {
+            self.buffer = String::from_utf8(bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+
+        Ok((read, too_long))
     }
 
-    output
+    /// Returns the todo that is finished by the next line
+    fn next_line(&mut self) -> Option<Todo> {
+        let text = split_terminator(&self.buffer).0;
+        self.line += 1;
+
+        if let Some(mut new_todo) = self.grammar.parse_line(text) {
+            new_todo.line = self.line;
+
+            return self.last_todo.replace(new_todo);
+        }
+
+        if let Some(todo) = &mut self.last_todo {
+            if let Some(comment) = comment(&todo.prefix, text) {
+                todo.comments.push(comment.to_owned())
+            }
+        }
+
+        None
+    }
 }
 
-// Config guarantees that keywords won't be empty
-fn todo_regex(keywords: &[String]) -> Regex {
-    let prefix = "(?P<prefix>.*)";
-    let keyword = format!("(?P<keyword>{})", keywords.join("|"));
-    let title = "(?P<title>.+)";
-    let issue_id = r"(\(#(?P<issue_id>\d+)\))";
+impl<'a, R: BufRead> Iterator for TodoReader<'a, R> {
+    type Item = io::Result<Todo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buffer.clear();
+
+            match self.read_line() {
+                Ok((0, _)) => return self.last_todo.take().map(Ok),
+                Ok((_, true)) => self.line += 1,
+                Ok((_, false)) => {
+                    if let Some(todo) = self.next_line() {
+                        return Some(Ok(todo));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
 
-    Regex::new(&format!("^{} {}{}?: {}$", prefix, keyword, issue_id, title)).unwrap()
+/// A change to the lines of a todo, only applied if the lines are still the same
+enum Edit {
+    Replace(String, String),
+    Remove(Vec<String>),
 }
 
-fn parse_line(regex: &Regex, text: &str) -> Option<Todo> {
-    regex.captures(&text).map(|c| Todo {
-        prefix: c.name("prefix").unwrap().as_str().to_owned(),
-        keyword: c.name("keyword").unwrap().as_str().to_owned(),
-        title: c.name("title").unwrap().as_str().to_owned(),
-        issue_id: c
-            .name("issue_id")
-            .map(|s| s.as_str().parse::<u32>().unwrap()),
-        comments: vec![],
-
-        // Line will be changed later
-        line: 0,
-    })
+impl Edit {
+    /// The lines the todo has to consist of
+    fn lines(&self) -> &[String] {
+        match self {
+            Edit::Replace(from, _) => std::slice::from_ref(from),
+            Edit::Remove(lines) => lines,
+        }
+    }
 }
 
-pub fn mark_todos<'a>(input: &'a str, todos: &[Todo]) -> Cow<'a, str> {
-    let mut map = HashMap::new();
-    let filtered_todos: Vec<_> = todos
-        .into_iter()
+fn mark_edits(grammar: &Grammar, todos: &[Todo]) -> Vec<(u32, Edit)> {
+    todos
+        .iter()
         .filter_map(|t| {
-            if let Some(reported) = t.reported_view() {
-                let unreported = t.unreported_pattern();
+            t.reported_view(grammar)
+                .map(|reported| (t.line, Edit::Replace(t.unreported_view(grammar), reported)))
+        })
+        .collect()
+}
 
-                map.insert(unreported.clone(), reported);
+fn remove_edits(grammar: &Grammar, todos: &[Todo]) -> Vec<(u32, Edit)> {
+    todos
+        .iter()
+        .filter_map(|t| t.reported_lines(grammar).map(|l| (t.line, Edit::Remove(l))))
+        .collect()
+}
 
-                Some(unreported)
-            } else {
+/// Copies the input and replaces the unreported todos with their reported form.
+/// Returns the lines of the todos that were changed.
+pub fn mark_todos<R: BufRead, W: Write>(
+    grammar: &Grammar,
+    input: R,
+    output: W,
+    todos: &[Todo],
+) -> io::Result<Vec<u32>> {
+    rewrite(input, output, mark_edits(grammar, todos))
+}
+
+/// Copies the input without the reported todos and their comments.
+/// Returns the lines of the todos that were removed.
+pub fn remove_todos<R: BufRead, W: Write>(
+    grammar: &Grammar,
+    input: R,
+    output: W,
+    todos: &[Todo],
+) -> io::Result<Vec<u32>> {
+    rewrite(input, output, remove_edits(grammar, todos))
+}
+
+/// A line of the input, only read completely if it could belong to an edit
+enum Line {
+    Whole(Vec<u8>),
+    Partial(Vec<u8>),
+}
+
+/// Reads the next line, but stops once it's longer than `limit` bytes without its line ending
+fn read_line<R: BufRead>(input: &mut R, limit: usize) -> io::Result<Option<Line>> {
+    let mut bytes = vec![];
+
+    loop {
+        let available = input.fill_buf()?;
+
+        if available.is_empty() {
+            return Ok(if bytes.is_empty() {
                 None
+            } else {
+                Some(Line::Whole(bytes))
+            });
+        }
+
+        // The line ending has at most two bytes
+        let room = limit + 3 - bytes.len();
+
+        if let Some(end) = available.iter().take(room).position(|b| *b == b'\n') {
+            bytes.extend_from_slice(&available[..=end]);
+            input.consume(end + 1);
+
+            return Ok(Some(Line::Whole(bytes)));
+        }
+
+        let len = available.len().min(room);
+        bytes.extend_from_slice(&available[..len]);
+        input.consume(len);
+
+        if bytes.len() == limit + 3 {
+            return Ok(Some(Line::Partial(bytes)));
+        }
+    }
+}
+
+/// Applies the edits to the first lines that still match them, in the order of their todos.
+/// Only these lines are kept in memory, the others are copied in chunks.
+/// The line endings and whether the file ends with one are kept.
+fn rewrite<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    mut edits: Vec<(u32, Edit)>,
+) -> io::Result<Vec<u32>> {
+    edits.sort_by_key(|(line, _)| *line);
+
+    let limit = edits
+        .iter()
+        .flat_map(|(_, edit)| edit.lines())
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+    let mut queues: HashMap<&[u8], VecDeque<(u32, &Edit)>> = HashMap::new();
+
+    for (line, edit) in &edits {
+        queues
+            .entry(edit.lines()[0].as_bytes())
+            .or_default()
+            .push_back((*line, edit));
+    }
+
+    let mut changed = vec![];
+    // Written with the next line, so removing the last line doesn't leave a line ending
+    let mut pending = "";
+    let mut ends_with_newline = false;
+    // Read while checking the lines of an edit, but not part of them
+    let mut next = None;
+
+    loop {
+        let bytes = match next
+            .take()
+            .map_or_else(|| read_line(&mut input, limit), |l| Ok(Some(l)))?
+        {
+            Some(Line::Whole(bytes)) => bytes,
+            Some(Line::Partial(bytes)) => {
+                pending = copy_line(&mut input, &mut output, &bytes, pending)?;
+                ends_with_newline = !pending.is_empty();
+                continue;
             }
-        })
-        .collect();
+            None => break,
+        };
 
-    if let Some(regex) = build_regex(&filtered_todos) {
-        regex.replace_all(input, |cap: &Captures| {
-            // Has to be escaped because the key is also escaped
-            let key = escape(cap.get(0).unwrap().as_str());
+        ends_with_newline = !split_ending(&bytes).1.is_empty();
 
-            map.get(&key).unwrap()
-        })
-    } else {
-        Cow::from(input)
+        let queue = match queues.get_mut(split_ending(&bytes).0) {
+            Some(queue) if !queue.is_empty() => queue,
+            _ => {
+                write_line(&mut output, &bytes, &mut pending)?;
+                continue;
+            }
+        };
+
+        let (line, edit) = queue[0];
+        let expected = edit.lines();
+        let mut block = vec![bytes];
+
+        while block.len() < expected.len() {
+            match read_line(&mut input, limit)? {
+                Some(Line::Whole(bytes))
+                    if split_ending(&bytes).0 == expected[block.len()].as_bytes() =>
+                {
+                    ends_with_newline = !split_ending(&bytes).1.is_empty();
+                    block.push(bytes)
+                }
+                other => {
+                    next = other;
+                    break;
+                }
+            }
+        }
+
+        if block.len() < expected.len() {
+            for l in &block {
+                write_line(&mut output, l, &mut pending)?
+            }
+
+            continue;
+        }
+
+        queue.pop_front();
+        changed.push(line);
+
+        if let Edit::Replace(_, to) = edit {
+            let replaced = [to.as_bytes(), split_ending(&block[0]).1.as_bytes()].concat();
+            write_line(&mut output, &replaced, &mut pending)?
+        }
+    }
+
+    if ends_with_newline {
+        output.write_all(pending.as_bytes())?
     }
+
+    output.flush()?;
+
+    Ok(changed)
 }
 
-fn build_regex(todos: &[String]) -> Option<Regex> {
-    if todos.len() == 0 {
-        None
-    } else {
-        Some(Regex::new(&format!("(?m){}", todos.join("|"))).unwrap())
+fn write_line<W: Write>(output: &mut W, line: &[u8], pending: &mut &'static str) -> io::Result<()> {
+    let (text, ending) = split_ending(line);
+
+    output.write_all(pending.as_bytes())?;
+    output.write_all(text)?;
+    *pending = ending;
+
+    Ok(())
+}
+
+/// Copies the rest of a line in chunks after writing its start,
+/// returns its line ending which isn't written yet
+fn copy_line<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    start: &[u8],
+    pending: &str,
+) -> io::Result<&'static str> {
+    output.write_all(pending.as_bytes())?;
+    // Held back, because it could belong to the line ending
+    let mut carriage_return = write_text(output, start)?;
+
+    loop {
+        let available = input.fill_buf()?;
+
+        if available.is_empty() {
+            if carriage_return {
+                output.write_all(b"\r")?
+            }
+
+            return Ok("");
+        }
+
+        let end = available.iter().position(|b| *b == b'\n');
+        let text = &available[..end.unwrap_or(available.len())];
+
+        if !text.is_empty() {
+            if carriage_return {
+                output.write_all(b"\r")?
+            }
+
+            carriage_return = write_text(output, text)?;
+        }
+
+        match end {
+            Some(end) => {
+                input.consume(end + 1);
+
+                return Ok(if carriage_return { "\r\n" } else { "\n" });
+            }
+            None => {
+                let len = available.len();
+                input.consume(len)
+            }
+        }
     }
 }
 
-pub fn remove_todos<'a>(input: &'a str, todos: &[Todo]) -> Cow<'a, str> {
-    let filtered_todos: Vec<_> = todos
-        .into_iter()
-        .filter_map(|t| t.reported_pattern())
-        .collect();
+/// Writes the bytes without a trailing carriage return, returns whether there was one
+fn write_text<W: Write>(output: &mut W, bytes: &[u8]) -> io::Result<bool> {
+    let text = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    output.write_all(text)?;
+
+    Ok(text.len() < bytes.len())
+}
 
-    if let Some(regex) = build_regex(&filtered_todos) {
-        regex.replace_all(input, "")
+/// Splits a line into its bytes and its line ending
+fn split_ending(line: &[u8]) -> (&[u8], &'static str) {
+    if let Some(text) = line.strip_suffix(b"\r\n") {
+        (text, "\r\n")
+    } else if let Some(text) = line.strip_suffix(b"\n") {
+        (text, "\n")
     } else {
-        Cow::from(input)
+        (line, "")
+    }
+}
+
+/// Splits a line into its text and its line ending
+fn split_terminator(line: &str) -> (&str, &'static str) {
+    let (text, ending) = split_ending(line.as_bytes());
+
+    (&line[..text.len()], ending)
+}
+
+/// The comment of a line that continues the todo, the line needs the same prefix
+fn comment<'a>(prefix: &str, text: &'a str) -> Option<&'a str> {
+    match text.strip_prefix(prefix)? {
+        "" => Some(""),
+        rest => rest.strip_prefix(' '),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::grammar::{Keyword, DEFAULT_REPORTED, DEFAULT_UNREPORTED};
+    use crate::todo::IssueId;
 
-    #[test]
-    fn create_regex() {
-        let reg = todo_regex(&vec!["TODO".to_owned(), "FIXME".to_owned()]);
+    fn find_str(grammar: &Grammar, input: &str) -> Vec<Todo> {
+        TodoReader::new(grammar, input.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn mark_str(grammar: &Grammar, input: &str, todos: &[Todo]) -> String {
+        let mut output = vec![];
+        mark_todos(grammar, input.as_bytes(), &mut output, todos).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    fn remove_str(grammar: &Grammar, input: &str, todos: &[Todo]) -> String {
+        let mut output = vec![];
+        remove_todos(grammar, input.as_bytes(), &mut output, todos).unwrap();
 
-        assert_eq!(true, reg.is_match("// TODO: Something"));
-        assert_eq!(true, reg.is_match("// FIXME: Something"));
-        assert_eq!(false, reg.is_match("// BUG: Something"));
-        assert_eq!(true, reg.is_match("// TODO(#123): Something"))
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn parse_keywords() {
+        let grammar = Grammar::new(
+            &[Keyword::new("TODO"), Keyword::new("FIXME")],
+            DEFAULT_UNREPORTED,
+            DEFAULT_REPORTED,
+        )
+        .unwrap();
+
+        assert!(grammar.parse_line("// TODO: Something").is_some());
+        assert!(grammar.parse_line("// FIXME: Something").is_some());
+        assert!(grammar.parse_line("// BUG: Something").is_none());
+        assert!(grammar.parse_line("// TODO(#123): Something").is_some())
     }
 
     mod parse_file {
@@ -143,12 +459,15 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
                 issue_id: None,
+                issue_repo: None,
                 comments: vec![],
             };
 
-            assert_eq!(vec![expected], find_todos(&vec!["TODO".to_owned()], input))
+            assert_eq!(vec![expected], find_str(&Grammar::default(), input))
         }
 
         #[test]
@@ -158,12 +477,15 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
                 issue_id: None,
+                issue_repo: None,
                 comments: vec!["More".to_owned(), "And more".to_owned()],
             };
 
-            assert_eq!(vec![expected], find_todos(&vec!["TODO".to_owned()], input))
+            assert_eq!(vec![expected], find_str(&Grammar::default(), input))
         }
 
         #[test]
@@ -173,12 +495,15 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
                 issue_id: None,
+                issue_repo: None,
                 comments: vec!["More".to_owned(), "And more".to_owned(), "".to_owned()],
             };
 
-            assert_eq!(vec![expected], find_todos(&vec!["TODO".to_owned()], input))
+            assert_eq!(vec![expected], find_str(&Grammar::default(), input))
         }
 
         #[test]
@@ -188,12 +513,15 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
                 issue_id: None,
+                issue_repo: None,
                 comments: vec!["More".to_owned(), r"And (\d+) more".to_owned()],
             };
 
-            assert_eq!(vec![expected], find_todos(&vec!["TODO".to_owned()], input))
+            assert_eq!(vec![expected], find_str(&Grammar::default(), input))
         }
 
         #[test]
@@ -203,12 +531,55 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
-                issue_id: Some(42),
+                issue_id: Some(42.into()),
+                issue_repo: None,
                 comments: vec![],
             };
 
-            assert_eq!(vec![expected], find_todos(&vec!["TODO".to_owned()], input))
+            assert_eq!(vec![expected], find_str(&Grammar::default(), input))
+        }
+
+        #[test]
+        fn parse_adversarial() {
+            let long_prefix = "/*(.*)[".repeat(10_000);
+            let input = format!(
+                "// TODO(#99999999999): Huge\ncode();\n{0} TODO: Long\n{0} More\n{0}\n",
+                long_prefix
+            );
+            let todos = find_str(&Grammar::default(), &input);
+
+            assert_eq!(2, todos.len());
+            assert_eq!(Some(IssueId::new("99999999999")), todos[0].issue_id);
+            assert_eq!(None, todos[0].issue_id.as_ref().and_then(IssueId::number));
+            assert_eq!(long_prefix, todos[1].prefix);
+            assert_eq!(vec!["More".to_owned(), "".to_owned()], todos[1].comments)
+        }
+
+        #[test]
+        fn skip_long_lines() {
+            let input = format!(
+                "// TODO: Something\n// {}\n// TODO: Other\n",
+                "x".repeat(100)
+            );
+            let todos: Vec<_> = TodoReader::new(&Grammar::default(), input.as_bytes())
+                .max_line_length(64)
+                .collect::<io::Result<_>>()
+                .unwrap();
+
+            assert_eq!(2, todos.len());
+            assert!(todos[0].comments.is_empty());
+            assert_eq!(3, todos[1].line)
+        }
+
+        #[test]
+        fn parse_invalid_utf8() {
+            let input: &[u8] = b"// TODO: Something\n// \xff\xfe\n";
+            let todos: Vec<_> = TodoReader::new(&Grammar::default(), input).collect();
+
+            assert!(todos.iter().any(|t| t.is_err()))
         }
 
         #[test]
@@ -218,8 +589,11 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
                 issue_id: None,
+                issue_repo: None,
                 comments: vec!["More".to_owned()],
             };
 
@@ -227,36 +601,97 @@ mod tests {
                 line: 3,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Other".to_owned(),
                 issue_id: None,
+                issue_repo: None,
                 comments: vec!["comment".to_owned()],
             };
 
             assert_eq!(
                 vec![expected_one, expected_two],
-                find_todos(&vec!["TODO".to_owned()], input)
+                find_str(&Grammar::default(), input)
             )
         }
     }
 
-    mod build_regex {
+    mod mark_todos {
         use super::*;
 
         #[test]
-        fn build_empty() {
-            assert_eq!(true, build_regex(&Vec::new()).is_none())
+        fn mark_returns_changed_lines() {
+            let input = "// TODO: Something\n\n// TODO: Other";
+            let mut todos = find_str(&Grammar::default(), input);
+            todos[0].issue_id = Some(1.into());
+            todos[1].issue_id = Some(2.into());
+            todos[1].title = "Changed".to_owned();
+
+            let mut output = vec![];
+            let changed =
+                mark_todos(&Grammar::default(), input.as_bytes(), &mut output, &todos).unwrap();
+
+            assert_eq!(vec![1], changed)
         }
 
         #[test]
-        fn build_with_todos() {
-            let regex = build_regex(&vec!["123".to_owned(), "456".to_owned(), "789".to_owned()]);
+        fn mark_keeps_line_endings() {
+            let input = "a\n// TODO: Something\r\nb";
+            let mut todos = find_str(&Grammar::default(), input);
+            todos[0].issue_id = Some(1.into());
 
-            assert_eq!("(?m)123|456|789", regex.unwrap().as_str())
+            assert_eq!(
+                "a\n// TODO(#1): Something\r\nb".to_owned(),
+                mark_str(&Grammar::default(), input, &todos)
+            )
         }
-    }
 
-    mod mark_todos {
-        use super::*;
+        #[test]
+        fn mark_long_lines() {
+            let long = format!("{}\r", "x".repeat(100));
+            let input = format!("// TODO: Something\r\n{0}\r\n// TODO: Other\n{0}", long);
+            let mut todos = find_str(&Grammar::default(), &input);
+            todos[0].issue_id = Some(1.into());
+            todos[1].issue_id = Some(2.into());
+
+            // Smaller than the long lines, so they are copied in chunks
+            let reader = io::BufReader::with_capacity(8, input.as_bytes());
+            let mut output = vec![];
+            let changed = mark_todos(&Grammar::default(), reader, &mut output, &todos).unwrap();
+
+            assert_eq!(vec![1, 3], changed);
+            assert_eq!(
+                format!(
+                    "// TODO(#1): Something\r\n{0}\r\n// TODO(#2): Other\n{0}",
+                    long
+                ),
+                String::from_utf8(output).unwrap()
+            )
+        }
+
+        #[test]
+        fn mark_custom_grammar() {
+            let grammar = Grammar::new(
+                &[Keyword::new("TODO")],
+                "{prefix} {keyword}({tag}): {title}",
+                "{prefix} {keyword}({tag}, {issue}): {title}",
+            )
+            .unwrap();
+            let input = "// TODO(john): Something\n\nSomething Else";
+
+            let todos = find_str(&grammar, input)
+                .into_iter()
+                .map(|mut t| {
+                    t.issue_id = Some(42.into());
+                    t
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                "// TODO(john, #42): Something\n\nSomething Else".to_owned(),
+                mark_str(&grammar, input, &todos)
+            )
+        }
 
         #[test]
         fn mark_single() {
@@ -265,14 +700,17 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
-                issue_id: Some(42),
+                issue_id: Some(42.into()),
+                issue_repo: None,
                 comments: vec![],
             };
 
             assert_eq!(
                 "// TODO(#42): Something\n\nSomething Else".to_owned(),
-                mark_todos(input, &vec![expected])
+                mark_str(&Grammar::default(), input, &[expected])
             )
         }
 
@@ -284,8 +722,11 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
-                issue_id: Some(123),
+                issue_id: Some(123.into()),
+                issue_repo: None,
                 comments: vec!["More".to_owned()],
             };
 
@@ -293,14 +734,17 @@ mod tests {
                 line: 3,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Other".to_owned(),
-                issue_id: Some(456),
+                issue_id: Some(456.into()),
+                issue_repo: None,
                 comments: vec!["comment".to_owned()],
             };
 
             assert_eq!(
                 "// TODO(#123): Something\n\nSomething Else\n// TODO(#456): Other".to_owned(),
-                mark_todos(input, &vec![todo_one, todo_two])
+                mark_str(&Grammar::default(), input, &[todo_one, todo_two])
             )
         }
     }
@@ -308,6 +752,29 @@ mod tests {
     mod remove_todos {
         use super::*;
 
+        #[test]
+        fn remove_with_line_endings() {
+            let input = "a\r\n// TODO(#1): Something\r\n// More\r\n//\r\nb\r\n";
+            let todos = find_str(&Grammar::default(), input);
+
+            assert_eq!(
+                "a\r\nb\r\n".to_owned(),
+                remove_str(&Grammar::default(), input, &todos)
+            )
+        }
+
+        #[test]
+        fn remove_changed_todo() {
+            let input = "// TODO(#1): Something\n// Changed";
+            let mut todos = find_str(&Grammar::default(), input);
+            todos[0].comments = vec!["Before".to_owned()];
+
+            assert_eq!(
+                input.to_owned(),
+                remove_str(&Grammar::default(), input, &todos)
+            )
+        }
+
         #[test]
         fn remove_simple() {
             let input = "// TODO(#42): Something\n\nSomething Else";
@@ -315,14 +782,17 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
-                issue_id: Some(42),
+                issue_id: Some(42.into()),
+                issue_repo: None,
                 comments: vec![],
             };
 
             assert_eq!(
                 "\nSomething Else".to_owned(),
-                remove_todos(input, &vec![expected])
+                remove_str(&Grammar::default(), input, &[expected])
             )
         }
 
@@ -334,8 +804,11 @@ mod tests {
                 line: 1,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Something".to_owned(),
-                issue_id: Some(123),
+                issue_id: Some(123.into()),
+                issue_repo: None,
                 comments: vec!["More".to_owned()],
             };
 
@@ -343,14 +816,59 @@ mod tests {
                 line: 3,
                 prefix: "//".to_owned(),
                 keyword: "TODO".to_owned(),
+                kind: "TODO".to_owned(),
+                tag: None,
                 title: "Other".to_owned(),
-                issue_id: Some(456),
+                issue_id: Some(456.into()),
+                issue_repo: None,
                 comments: vec![],
             };
 
             assert_eq!(
-                "Something Else\n".to_owned(),
-                remove_todos(input, &vec![todo_one, todo_two])
+                "Something Else".to_owned(),
+                remove_str(&Grammar::default(), input, &[todo_one, todo_two])
+            )
+        }
+
+        #[test]
+        fn remove_before_long_line() {
+            let long = "x".repeat(100);
+            let input = format!("// TODO(#1): Something\n// More\n{}\n", long);
+            let todos = find_str(&Grammar::default(), &input);
+
+            let reader = io::BufReader::with_capacity(8, input.as_bytes());
+            let mut output = vec![];
+            let changed = remove_todos(&Grammar::default(), reader, &mut output, &todos).unwrap();
+
+            assert_eq!(vec![1], changed);
+            assert_eq!(format!("{}\n", long), String::from_utf8(output).unwrap())
+        }
+
+        #[test]
+        fn remove_custom_grammar() {
+            let grammar = Grammar::new(
+                &[Keyword::new("FIXME")],
+                "{prefix} {keyword}: {title}",
+                "{prefix} {keyword} [{issue}]: {title}",
+            )
+            .unwrap();
+            let input = "# FIXME [#9]: Something\n# More\nSomething Else";
+
+            let todo = Todo {
+                line: 1,
+                prefix: "#".to_owned(),
+                keyword: "FIXME".to_owned(),
+                kind: "FIXME".to_owned(),
+                tag: None,
+                title: "Something".to_owned(),
+                issue_id: Some(9.into()),
+                issue_repo: None,
+                comments: vec!["More".to_owned()],
+            };
+
+            assert_eq!(
+                "Something Else".to_owned(),
+                remove_str(&grammar, input, &[todo])
             )
         }
     }